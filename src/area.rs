@@ -0,0 +1,437 @@
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter};
+use std::path::Path;
+use std::sync::Arc;
+use std::collections::HashMap;
+use osm_pbf_iter::Primitive;
+
+use item::{Item, ItemSpecific, ItemId, ItemType};
+use element::Element;
+use set::Set;
+use locations::Locations;
+use geometry::{assemble_area, Ring, Polygon, MultiPolygon};
+use process::{Runner, Worker};
+use query::QueryTarget;
+use binary::*;
+
+/// Overpass offsets area ids to keep them apart from the ids of the
+/// elements they are derived from
+pub const WAY_AREA_OFFSET: u64 = 2400000000;
+pub const RELATION_AREA_OFFSET: u64 = 3600000000;
+
+/// Tag keys that make a named, closed way an area
+const AREA_KEYS: &[&str] = &[
+    "abandoned", "amenity", "boundary", "building", "building:part",
+    "craft", "historic", "landuse", "leisure", "man_made", "military",
+    "natural", "office", "place", "shop", "sport", "tourism",
+];
+
+const MAGIC: &[u8; 8] = b"UTAREAS2";
+
+/// Which elements Overpass derives areas from. Relations need a
+/// name plus `admin_level` or `type=multipolygon`, or a postal
+/// code. Ways must be named and carry `area=yes` or a key of
+/// `AREA_KEYS`. Closedness of ways is checked on assembly.
+pub fn is_area_candidate<E: Element>(item: &E) -> bool {
+    let has = |key| item.tag(key).is_some();
+    let item_type = item.item_id().item_type;
+    match item_type {
+        ItemType::Relation =>
+            (has("name") && has("admin_level")) ||
+            (has("name") && item.tag("type") == Some("multipolygon")) ||
+            has("postal_code") ||
+            (has("addr:postcode") && item.tag("boundary") == Some("postal_code")),
+        ItemType::Way =>
+            has("name") && has_area_tags(item),
+        _ =>
+            false,
+    }
+}
+
+/// Would a closed way with these tags be an area rather than a
+/// closed line?
+pub fn has_area_tags<E: Element>(item: &E) -> bool {
+    item.tag("area") != Some("no") &&
+        (item.tag("area") == Some("yes") ||
+         AREA_KEYS.iter().any(|key| item.tag(key).is_some()))
+}
+
+pub fn area_id(item_id: &ItemId) -> Option<u64> {
+    match item_id.item_type {
+        ItemType::Way =>
+            Some(item_id.id + WAY_AREA_OFFSET),
+        ItemType::Relation =>
+            Some(item_id.id + RELATION_AREA_OFFSET),
+        _ =>
+            None,
+    }
+}
+
+/// Scan worker collecting area candidates
+struct Candidates {
+    items: Vec<Item>,
+}
+
+impl Worker for Candidates {
+    fn process(&mut self, primitive: &Primitive) {
+        if is_area_candidate(primitive) {
+            self.items.push(primitive.into());
+        }
+    }
+}
+
+/// All derived areas of a dataset, persisted next to the first PBF
+/// file so that only the first query needs to build them. The file
+/// records which versions of the PBF files it was built from.
+#[derive(Debug)]
+pub struct AreaStore {
+    areas: Set,
+    index: AreaIndex,
+}
+
+impl AreaStore {
+    fn new(areas: Set) -> Self {
+        let index = AreaIndex::new(&areas);
+        AreaStore { areas, index }
+    }
+
+    /// Loads the area file if it was built from the same PBF files,
+    /// builds and saves it otherwise. `get_locations` is only called
+    /// when building.
+    pub fn open<F>(runner: &Runner, get_locations: F) -> Self
+    where
+        F: FnOnce() -> Arc<Locations>,
+    {
+        let paths = runner.source().paths();
+        let path = sidecar_path(&paths[0], "areas");
+        let stamps = paths.iter()
+            .map(|pbf_path| SourceStamp::new(pbf_path)
+                 .unwrap_or_else(|e| panic!("Cannot stat {}: {}", pbf_path.display(), e)))
+            .collect::<Vec<_>>();
+        if path.exists() {
+            match AreaStore::load(&path, &stamps) {
+                Ok(store) => return store,
+                Err(e) => eprintln!("Cannot load {}: {}", path.display(), e),
+            }
+        }
+
+        eprintln!("Building areas");
+        let store = AreaStore::build(runner, &get_locations());
+        if let Err(e) = store.save(&path, &stamps) {
+            eprintln!("Cannot save {}: {}", path.display(), e);
+        }
+        store
+    }
+
+    pub fn build(runner: &Runner, locations: &Locations) -> Self {
+        let workers = runner.scan_all(|| Candidates { items: vec![] });
+        let candidates = workers.into_iter()
+            .flat_map(|candidates| candidates.items);
+        AreaStore::from_candidates(candidates, locations)
+    }
+
+    fn from_candidates<I>(candidates: I, locations: &Locations) -> Self
+    where
+        I: Iterator<Item=Item>,
+    {
+        let mut areas = Set::empty();
+        for item in candidates {
+            let id = match area_id(&item.item_id()) {
+                Some(id) => id,
+                None => continue,
+            };
+            let assembly = match assemble_area(&item, locations) {
+                Some(assembly) => assembly,
+                None => continue,
+            };
+            if assembly.multipolygon.is_empty() {
+                continue;
+            }
+            areas.insert(Item::new(id, item.tags, ItemSpecific::Area {
+                multipolygon: Arc::new(assembly.multipolygon),
+            }));
+        }
+        AreaStore::new(areas)
+    }
+
+    pub fn len(&self) -> usize {
+        self.areas.len()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Item> {
+        self.areas.get(&ItemId::new(ItemType::Area, id))
+    }
+
+    /// Areas whose polygons contain the point
+    pub fn containing<'a>(&'a self, point: (f64, f64)) -> impl Iterator<Item=&'a Item> {
+        self.index.candidates(point)
+            .filter_map(move |id| self.get(*id))
+            .filter(move |area| match area.specific() {
+                &ItemSpecific::Area { ref multipolygon } =>
+                    multipolygon.contains(point),
+                _ =>
+                    false,
+            })
+    }
+
+    /// Areas containing any of the points, for `is_in`
+    pub fn is_in<I>(&self, points: I) -> Set
+    where
+        I: Iterator<Item=(f64, f64)>,
+    {
+        let mut results = Set::empty();
+        for point in points {
+            for area in self.containing(point) {
+                if !results.contains(area) {
+                    results.insert(area.clone());
+                }
+            }
+        }
+        results
+    }
+
+    /// Evaluates a query target on all areas
+    pub fn query(&self, target: &QueryTarget) -> Set {
+        let mut results = Set::empty();
+        for area in self.areas.iter() {
+            if target.matches(area) {
+                results.insert(area.clone());
+            }
+        }
+        results
+    }
+
+    pub fn save(&self, path: &Path, stamps: &[SourceStamp]) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        write_stamps(&mut file, stamps)?;
+        write_u64(&mut file, self.areas.len() as u64)?;
+        for area in self.areas.iter() {
+            write_u64(&mut file, area.id)?;
+            write_u64(&mut file, area.tags.len() as u64)?;
+            for (k, v) in area.tags.iter() {
+                write_str(&mut file, k)?;
+                write_str(&mut file, v)?;
+            }
+            let multipolygon = match area.specific() {
+                &ItemSpecific::Area { ref multipolygon } => multipolygon,
+                _ => unreachable!(),
+            };
+            write_u64(&mut file, multipolygon.polygons.len() as u64)?;
+            for polygon in multipolygon.polygons.iter() {
+                write_ring(&mut file, &polygon.outer)?;
+                write_u64(&mut file, polygon.inners.len() as u64)?;
+                for inner in polygon.inners.iter() {
+                    write_ring(&mut file, inner)?;
+                }
+            }
+        }
+        file.flush()
+    }
+
+    /// Fails if built from other than `stamps`
+    pub fn load(path: &Path, stamps: &[SourceStamp]) -> io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        read_magic(&mut file, MAGIC)?;
+        if read_stamps(&mut file)? != stamps {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Built from other PBF files"));
+        }
+        let mut areas = Set::empty();
+        for _ in 0..read_u64(&mut file)? {
+            let id = read_u64(&mut file)?;
+            let mut tags = HashMap::new();
+            for _ in 0..read_u64(&mut file)? {
+                let k = read_str(&mut file)?;
+                let v = read_str(&mut file)?;
+                tags.insert(k, v);
+            }
+            let mut polygons = vec![];
+            for _ in 0..read_u64(&mut file)? {
+                let outer = read_ring(&mut file)?;
+                let mut inners = vec![];
+                for _ in 0..read_u64(&mut file)? {
+                    inners.push(read_ring(&mut file)?);
+                }
+                polygons.push(Polygon { outer, inners });
+            }
+            areas.insert(Item::new(id, tags, ItemSpecific::Area {
+                multipolygon: Arc::new(MultiPolygon::new(polygons)),
+            }));
+        }
+        Ok(AreaStore::new(areas))
+    }
+}
+
+/// Grid of 1° cells, listing the areas whose bounding boxes overlap
+/// each cell
+#[derive(Debug)]
+struct AreaIndex {
+    cells: HashMap<(i32, i32), Vec<u64>>,
+}
+
+impl AreaIndex {
+    fn new(areas: &Set) -> Self {
+        let mut cells = HashMap::new();
+        for area in areas.iter() {
+            let bbox = match area.specific() {
+                &ItemSpecific::Area { ref multipolygon } =>
+                    multipolygon.bbox(),
+                _ =>
+                    None,
+            };
+            let bbox = match bbox {
+                Some(bbox) => bbox,
+                None => continue,
+            };
+            let (s, w) = cell((bbox.s, bbox.w));
+            let (n, e) = cell((bbox.n, bbox.e));
+            for lat in s..(n + 1) {
+                for lon in w..(e + 1) {
+                    cells.entry((lat, lon))
+                        .or_insert_with(Vec::new)
+                        .push(area.id);
+                }
+            }
+        }
+        AreaIndex { cells }
+    }
+
+    fn candidates<'a>(&'a self, point: (f64, f64)) -> impl Iterator<Item=&'a u64> {
+        self.cells.get(&cell(point))
+            .into_iter()
+            .flat_map(|ids| ids.iter())
+    }
+}
+
+fn cell((lat, lon): (f64, f64)) -> (i32, i32) {
+    (lat.floor() as i32, lon.floor() as i32)
+}
+
+fn write_ring<W: Write>(w: &mut W, ring: &Ring) -> io::Result<()> {
+    write_u64(w, ring.points.len() as u64)?;
+    for &(lat, lon) in ring.points.iter() {
+        write_f64(w, lat)?;
+        write_f64(w, lon)?;
+    }
+    Ok(())
+}
+
+fn read_ring<R: Read>(r: &mut R) -> io::Result<Ring> {
+    let mut points = vec![];
+    for _ in 0..read_u64(r)? {
+        let lat = read_f64(r)?;
+        let lon = read_f64(r)?;
+        points.push((lat, lon));
+    }
+    Ok(Ring::new(points))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use locations::Locations;
+    use binary::SourceStamp;
+    use super::{is_area_candidate, AreaStore};
+
+    fn tags(tags: &[(&str, &str)]) -> HashMap<String, String> {
+        tags.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    fn way(id: u64, tag_list: &[(&str, &str)]) -> Item {
+        Item::new(id, tags(tag_list), ItemSpecific::Way { refs: vec![1, 2, 3, 1] })
+    }
+
+    #[test]
+    fn test_candidates() {
+        assert!(is_area_candidate(&way(1, &[("building", "yes"), ("name", "C3D2")])));
+        assert!(is_area_candidate(&way(1, &[("area", "yes"), ("name", "Altmarkt")])));
+        assert!(!is_area_candidate(&way(1, &[("building", "yes")])));
+        assert!(!is_area_candidate(&way(1, &[("highway", "pedestrian"), ("name", "Altmarkt")])));
+        assert!(!is_area_candidate(&way(1, &[("landuse", "grass"), ("name", "Wiese"), ("area", "no")])));
+
+        let relation = |tag_list| Item::new(1, tags(tag_list), ItemSpecific::Relation { members: vec![] });
+        assert!(is_area_candidate(&relation(&[("type", "boundary"), ("admin_level", "6"), ("name", "Dresden")])));
+        assert!(is_area_candidate(&relation(&[("type", "multipolygon"), ("name", "Großer Garten")])));
+        assert!(is_area_candidate(&relation(&[("postal_code", "01067")])));
+        assert!(!is_area_candidate(&relation(&[("type", "multipolygon"), ("landuse", "forest")])));
+    }
+
+    #[test]
+    fn test_build_save_load() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.0);
+        locations.insert_node(2, 51.0, 14.0);
+        locations.insert_node(3, 52.0, 14.0);
+        locations.insert_way(10, vec![1, 2, 3, 1]);
+        let relation = Item::new(20, tags(&[("type", "multipolygon"), ("name", "Dreieck")]), ItemSpecific::Relation {
+            members: vec![("outer".to_owned(), ItemId::new(ItemType::Way, 10))],
+        });
+        let candidates = vec![way(10, &[("building", "yes"), ("name", "C3D2")]), relation];
+        let store = AreaStore::from_candidates(candidates.into_iter(), &locations);
+        assert_eq!(store.len(), 2);
+        let way_area = store.get(2400000010).unwrap();
+        assert_eq!(way_area.tags.get("name").unwrap(), "C3D2");
+        assert!(store.get(3600000020).is_some());
+
+        let path = env::temp_dir().join(format!("underpass-turbo-test-{}.areas", process::id()));
+        let stamp = SourceStamp { path: "a.osm.pbf".to_owned(), size: 1000, modified: 1 };
+        store.save(&path, &[stamp.clone()]).unwrap();
+        let loaded = AreaStore::load(&path, &[stamp.clone()]);
+        let changed = SourceStamp { size: 1001, ..stamp.clone() };
+        let stale = AreaStore::load(&path, &[changed]);
+        let added = AreaStore::load(&path, &[stamp.clone(), stamp]);
+        fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.len(), 2);
+        let loaded_area = loaded.get(2400000010).unwrap();
+        assert_eq!(loaded_area.tags, way_area.tags);
+        assert_eq!(loaded_area.specific(), way_area.specific());
+        assert!(stale.is_err());
+        assert!(added.is_err());
+    }
+
+    #[test]
+    fn test_is_in() {
+        let mut locations = Locations::new();
+        // Two overlapping squares, spanning several index cells
+        locations.insert_node(1, 50.0, 12.0);
+        locations.insert_node(2, 50.0, 14.0);
+        locations.insert_node(3, 52.0, 14.0);
+        locations.insert_node(4, 52.0, 12.0);
+        locations.insert_node(5, 51.0, 13.0);
+        locations.insert_node(6, 51.0, 15.0);
+        locations.insert_node(7, 53.0, 15.0);
+        locations.insert_node(8, 53.0, 13.0);
+        let candidates = vec![
+            Item::new(10, tags(&[("landuse", "forest"), ("name", "A")]), ItemSpecific::Way {
+                refs: vec![1, 2, 3, 4, 1],
+            }),
+            Item::new(11, tags(&[("landuse", "forest"), ("name", "B")]), ItemSpecific::Way {
+                refs: vec![5, 6, 7, 8, 5],
+            }),
+        ];
+        for item in candidates.iter() {
+            locations.insert_way(item.id, item.member_ids().iter().map(|id| id.id as i64).collect());
+        }
+        let store = AreaStore::from_candidates(candidates.into_iter(), &locations);
+
+        let ids = |points: Vec<(f64, f64)>| {
+            let mut ids = store.is_in(points.into_iter())
+                .into_iter()
+                .map(|area| area.id)
+                .collect::<Vec<_>>();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(vec![(50.5, 12.5)]), vec![2400000010]);
+        assert_eq!(ids(vec![(51.5, 13.5)]), vec![2400000010, 2400000011]);
+        assert_eq!(ids(vec![(50.5, 12.5), (52.5, 14.5)]), vec![2400000010, 2400000011]);
+        assert_eq!(ids(vec![(40.0, 12.5)]), Vec::<u64>::new());
+    }
+}
@@ -0,0 +1,282 @@
+use geodesic::EARTH_RADIUS;
+
+/// Rectangle in latitude/longitude coordinates. Like in Overpass,
+/// `w > e` wraps around the 180° meridian.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct BoundingBox {
+    pub s: f64,
+    pub w: f64,
+    pub n: f64,
+    pub e: f64,
+}
+
+impl BoundingBox {
+    pub fn new(s: f64, w: f64, n: f64, e: f64) -> Self {
+        BoundingBox { s, w, n, e }
+    }
+
+    /// Smallest bounding box around the points. Wraps around the
+    /// 180° meridian when that is narrower, for output of elements
+    /// that cross it.
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item=(f64, f64)>,
+    {
+        let points = points.into_iter().collect::<Vec<_>>();
+        let bbox = BoundingBox::planar_from_points(points.iter().cloned())?;
+        // The same points with western longitudes moved past 180°
+        let shifted = BoundingBox::planar_from_points(
+            points.iter()
+                .map(|&(lat, lon)| (lat, if lon < 0.0 { lon + 360.0 } else { lon }))
+        )?;
+        if shifted.e - shifted.w < bbox.e - bbox.w {
+            let unshift = |lon: f64| if lon > 180.0 { lon - 360.0 } else { lon };
+            Some(BoundingBox::new(bbox.s, unshift(shifted.w), bbox.n, unshift(shifted.e)))
+        } else {
+            Some(bbox)
+        }
+    }
+
+    /// Smallest bounding box around the points on the plane spanned
+    /// by longitude and latitude. Does not wrap, like the segment
+    /// and ring tests that filters use.
+    pub fn planar_from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item=(f64, f64)>,
+    {
+        let mut points = points.into_iter();
+        let (lat, lon) = points.next()?;
+        let mut bbox = BoundingBox::new(lat, lon, lat, lon);
+        for (lat, lon) in points {
+            bbox.s = bbox.s.min(lat);
+            bbox.w = bbox.w.min(lon);
+            bbox.n = bbox.n.max(lat);
+            bbox.e = bbox.e.max(lon);
+        }
+        Some(bbox)
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        let lat = (self.s + self.n) / 2.0;
+        let lon = if self.wraps() {
+            let lon = (self.w + self.e + 360.0) / 2.0;
+            if lon > 180.0 { lon - 360.0 } else { lon }
+        } else {
+            (self.w + self.e) / 2.0
+        };
+        (lat, lon)
+    }
+
+    /// Crosses the 180° meridian?
+    pub fn wraps(&self) -> bool {
+        self.w > self.e
+    }
+
+    /// Parts on either side of the 180° meridian if `wraps()`,
+    /// otherwise just `self`
+    pub fn split(&self) -> Vec<BoundingBox> {
+        if self.wraps() {
+            vec![
+                BoundingBox::new(self.s, self.w, self.n, 180.0),
+                BoundingBox::new(self.s, -180.0, self.n, self.e),
+            ]
+        } else {
+            vec![*self]
+        }
+    }
+
+    pub fn contains(&self, lat: f64, lon: f64) -> bool {
+        let lon_inside = if self.wraps() {
+            self.w <= lon || lon <= self.e
+        } else {
+            self.w <= lon && lon <= self.e
+        };
+        self.s <= lat && lat <= self.n && lon_inside
+    }
+
+    /// Is any part of the line segment inside?
+    pub fn intersects_segment(&self, a: (f64, f64), b: (f64, f64)) -> bool {
+        if self.wraps() {
+            self.split().iter()
+                .any(|part| part.intersects_segment(a, b))
+        } else {
+            self.clips_segment(a, b)
+        }
+    }
+
+    /// Liang-Barsky clipping on the plane spanned by longitude and
+    /// latitude. Only for bounding boxes that do not wrap.
+    fn clips_segment(&self, (lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> bool {
+        let dlon = lon2 - lon1;
+        let dlat = lat2 - lat1;
+        let mut t0 = 0.0;
+        let mut t1 = 1.0;
+        for &(p, q) in &[
+            (-dlon, lon1 - self.w),
+            (dlon, self.e - lon1),
+            (-dlat, lat1 - self.s),
+            (dlat, self.n - lat1),
+        ] {
+            if p == 0.0 {
+                // Parallel to this edge
+                if q < 0.0 {
+                    return false;
+                }
+            } else {
+                let r = q / p;
+                if p < 0.0 {
+                    if r > t1 {
+                        return false;
+                    } else if r > t0 {
+                        t0 = r;
+                    }
+                } else {
+                    if r < t0 {
+                        return false;
+                    } else if r < t1 {
+                        t1 = r;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Grown by `meters` on every side, without wrapping. Covers all
+    /// points within that distance.
+    pub fn expand(&self, meters: f64) -> BoundingBox {
+        let dlat = (meters / EARTH_RADIUS).to_degrees();
+        let max_lat = self.s.abs().max(self.n.abs()) + dlat;
+        let dlon = if max_lat >= 90.0 {
+            360.0
+        } else {
+            dlat / max_lat.to_radians().cos()
+        };
+        BoundingBox::new(
+            (self.s - dlat).max(-90.0),
+            (self.w - dlon).max(-180.0),
+            (self.n + dlat).min(90.0),
+            (self.e + dlon).min(180.0)
+        )
+    }
+
+    /// Do the boxes overlap?
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.split().iter()
+            .any(|a| other.split().iter()
+                 .any(|b| a.s <= b.n && b.s <= a.n && a.w <= b.e && b.w <= a.e))
+    }
+
+    /// Is any point inside, or does any segment cross?
+    pub fn intersects_line<I>(&self, points: I) -> bool
+    where
+        I: IntoIterator<Item=(f64, f64)>,
+    {
+        let mut prev = None;
+        for point in points {
+            let is_match = match prev {
+                None =>
+                    self.contains(point.0, point.1),
+                Some(prev) =>
+                    self.intersects_segment(prev, point),
+            };
+            if is_match {
+                return true;
+            }
+            prev = Some(point);
+        }
+        false
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::BoundingBox;
+
+    #[test]
+    fn test_contains() {
+        let bbox = BoundingBox::new(51.0, 13.0, 52.0, 14.0);
+        assert!(bbox.contains(51.5, 13.5));
+        assert!(bbox.contains(51.0, 14.0));
+        assert!(!bbox.contains(50.9, 13.5));
+        assert!(!bbox.contains(51.5, 14.1));
+    }
+
+    #[test]
+    fn test_segment_crossing() {
+        let bbox = BoundingBox::new(51.0, 13.0, 52.0, 14.0);
+        // Both ends outside, crossing the box
+        assert!(bbox.intersects_segment((51.5, 12.0), (51.5, 15.0)));
+        assert!(bbox.intersects_segment((50.0, 12.0), (53.0, 15.0)));
+        // Passing by
+        assert!(!bbox.intersects_segment((50.0, 12.0), (50.0, 15.0)));
+        assert!(!bbox.intersects_segment((50.0, 13.5), (51.5, 15.5)));
+    }
+
+    #[test]
+    fn test_antimeridian() {
+        // Around Fiji
+        let bbox = BoundingBox::new(-21.0, 177.0, -12.0, -178.0);
+        assert!(bbox.wraps());
+        assert!(bbox.contains(-18.0, 178.4));
+        assert!(bbox.contains(-18.0, 180.0));
+        assert!(bbox.contains(-18.0, -180.0));
+        assert!(bbox.intersects(&BoundingBox::new(-19.0, -179.0, -18.0, -170.0)));
+        assert!(!bbox.intersects(&BoundingBox::new(-19.0, 170.0, -18.0, 176.0)));
+        assert!(bbox.contains(-16.0, -179.9));
+        assert!(!bbox.contains(-18.0, 0.0));
+        assert!(!bbox.contains(-18.0, 176.0));
+        assert!(!bbox.contains(-18.0, -177.0));
+        assert!(!bbox.contains(-22.0, 179.0));
+        assert_eq!(bbox.split(), vec![
+            BoundingBox::new(-21.0, 177.0, -12.0, 180.0),
+            BoundingBox::new(-21.0, -180.0, -12.0, -178.0),
+        ]);
+
+        // Crossing either part
+        assert!(bbox.intersects_segment((-25.0, 179.0), (-10.0, 179.0)));
+        assert!(bbox.intersects_segment((-25.0, -179.0), (-10.0, -179.0)));
+        // Between the parts, on the other side of the world
+        assert!(!bbox.intersects_segment((-25.0, 0.0), (-10.0, 0.0)));
+        assert!(bbox.intersects_line(vec![(-30.0, -179.5), (-16.0, -179.5)]));
+        assert!(!bbox.intersects_line(vec![(-30.0, 170.0), (-16.0, 170.0)]));
+
+        // Not wrapping
+        let bbox = BoundingBox::new(-21.0, -180.0, -12.0, 180.0);
+        assert!(!bbox.wraps());
+        assert!(bbox.contains(-18.0, 0.0));
+    }
+
+    #[test]
+    fn test_from_points_center() {
+        let bbox = BoundingBox::from_points(vec![(51.0, 14.0), (52.0, 13.0), (51.5, 13.5)]).unwrap();
+        assert_eq!(bbox, BoundingBox::new(51.0, 13.0, 52.0, 14.0));
+        assert_eq!(bbox.center(), (51.5, 13.5));
+        assert!(BoundingBox::from_points(vec![]).is_none());
+        assert_eq!(BoundingBox::new(-20.0, 178.0, -10.0, -176.0).center(), (-15.0, -179.0));
+    }
+
+    #[test]
+    fn test_from_points_antimeridian() {
+        let points = vec![(-17.0, 179.5), (-18.0, -179.5), (-17.5, 179.8)];
+        let bbox = BoundingBox::from_points(points.clone()).unwrap();
+        assert_eq!(bbox, BoundingBox::new(-18.0, 179.5, -17.0, -179.5));
+        assert!(bbox.wraps());
+        assert_eq!(bbox.center(), (-17.5, 180.0));
+        assert_eq!(BoundingBox::planar_from_points(points).unwrap(),
+                   BoundingBox::new(-18.0, -179.5, -17.0, 179.8));
+        // A wide box that does not cross is kept as it is
+        let wide = BoundingBox::from_points(vec![(0.0, -50.0), (0.0, 50.0)]).unwrap();
+        assert!(!wide.wraps());
+    }
+
+    #[test]
+    fn test_line() {
+        let bbox = BoundingBox::new(51.0, 13.0, 52.0, 14.0);
+        assert!(bbox.intersects_line(vec![(51.5, 13.5)]));
+        assert!(bbox.intersects_line(vec![(50.0, 13.5), (50.5, 13.5), (53.0, 13.5)]));
+        assert!(!bbox.intersects_line(vec![(50.0, 13.5), (50.5, 13.5)]));
+        assert!(!bbox.intersects_line(vec![]));
+    }
+}
@@ -0,0 +1,135 @@
+//! Little-endian encoding for the files we keep next to the PBF
+//! files
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `planet.osm.pbf` gets `planet.osm.pbf.<extension>`
+pub fn sidecar_path(pbf_path: &Path, extension: &str) -> PathBuf {
+    let mut path = pbf_path.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Is the file newer than all of `sources`?
+pub fn is_fresh<'a, I>(path: &Path, sources: I) -> bool
+where
+    I: IntoIterator<Item=&'a Path>,
+{
+    let file_time = match modified(path) {
+        Some(file_time) => file_time,
+        None => return false,
+    };
+    sources.into_iter()
+        .all(|source| modified(source)
+             .map(|source_time| source_time <= file_time)
+             .unwrap_or(false)
+        )
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Identifies a version of a source file, for files derived from
+/// several of them
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceStamp {
+    pub path: String,
+    pub size: u64,
+    /// Nanoseconds since the epoch
+    pub modified: u64,
+}
+
+impl SourceStamp {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos()))
+            .unwrap_or(0);
+        Ok(SourceStamp {
+            path: path.to_string_lossy().into_owned(),
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+pub fn write_stamps<W: Write>(w: &mut W, stamps: &[SourceStamp]) -> io::Result<()> {
+    write_u64(w, stamps.len() as u64)?;
+    for stamp in stamps {
+        write_str(w, &stamp.path)?;
+        write_u64(w, stamp.size)?;
+        write_u64(w, stamp.modified)?;
+    }
+    Ok(())
+}
+
+pub fn read_stamps<R: Read>(r: &mut R) -> io::Result<Vec<SourceStamp>> {
+    let mut stamps = vec![];
+    for _ in 0..read_u64(r)? {
+        let path = read_str(r)?;
+        let size = read_u64(r)?;
+        let modified = read_u64(r)?;
+        stamps.push(SourceStamp { path, size, modified });
+    }
+    Ok(stamps)
+}
+
+pub fn read_magic<R: Read>(r: &mut R, magic: &[u8; 8]) -> io::Result<()> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    if &buf != magic {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected file type"));
+    }
+    Ok(())
+}
+
+pub fn write_u64<W: Write>(w: &mut W, n: u64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub fn write_i32<W: Write>(w: &mut W, n: i32) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub fn write_f64<W: Write>(w: &mut W, n: f64) -> io::Result<()> {
+    w.write_all(&n.to_le_bytes())
+}
+
+pub fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+pub fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn read_i32<R: Read>(r: &mut R) -> io::Result<i32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+pub fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+pub fn read_str<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = read_u64(r)? as usize;
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
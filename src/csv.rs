@@ -0,0 +1,185 @@
+use std::io::{self, Write};
+
+use item::Item;
+use set::Set;
+use locations::Locations;
+use ql::{OutputGeometry, CsvFormat, CsvColumn};
+use output::{self, Geometry};
+use measure;
+use writer::{Writer, sorted_items, type_name};
+
+/// One line per element, `[out:csv(...)]`
+pub struct CsvWriter<W: Write> {
+    out: W,
+    format: CsvFormat,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(out: W, format: CsvFormat) -> Self {
+        CsvWriter { out, format }
+    }
+
+    fn write_line<'a, I: Iterator<Item=&'a str>>(&mut self, fields: I) -> io::Result<()> {
+        let line = fields
+            .map(|field| self.quote(field))
+            .collect::<Vec<_>>()
+            .join(&self.format.separator);
+        writeln!(self.out, "{}", line)
+    }
+
+    /// Fields containing the separator, quotes or line breaks are
+    /// quoted for spreadsheets
+    fn quote(&self, field: &str) -> String {
+        if field.contains(self.format.separator.as_str()) ||
+            field.contains(|c| c == '"' || c == '\n' || c == '\r')
+        {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_owned()
+        }
+    }
+}
+
+impl<W: Write> Writer for CsvWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        if self.format.header {
+            let names = self.format.columns.iter()
+                .map(|column| column.name().to_owned())
+                .collect::<Vec<_>>();
+            self.write_line(names.iter().map(|name| name.as_str()))?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> io::Result<()> {
+        let needs_lat_lon = self.format.columns.iter()
+            .any(|column| *column == CsvColumn::Lat || *column == CsvColumn::Lon);
+        for item in sorted_items(set) {
+            // Resolved once for both coordinate columns
+            let lat_lon = if needs_lat_lon { lat_lon(item, geometry, locations) } else { None };
+            let fields = self.format.columns.iter()
+                .map(|column| field(item, column, lat_lon, locations))
+                .collect::<Vec<_>>();
+            self.write_line(fields.iter().map(|field| field.as_str()))?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn needs_locations(&self) -> bool {
+        self.format.columns.iter()
+            .any(|column| *column == CsvColumn::Length || *column == CsvColumn::Area)
+    }
+}
+
+/// Of nodes, or the center of ways and relations with `out center`
+fn lat_lon(item: &Item, geometry: &OutputGeometry, locations: Option<&Locations>) -> Option<(f64, f64)> {
+    if item.is_node() {
+        return item.get_lat_lon();
+    }
+    if *geometry != OutputGeometry::Center {
+        return None;
+    }
+    match locations.and_then(|locations| output::resolve(item, geometry, locations)) {
+        Some(Geometry::Center(lat, lon)) => Some((lat, lon)),
+        _ => None,
+    }
+}
+
+/// Empty when not applicable
+fn field(item: &Item, column: &CsvColumn, lat_lon: Option<(f64, f64)>, locations: Option<&Locations>) -> String {
+    match column {
+        &CsvColumn::Tag(ref key) =>
+            item.tags.get(key)
+            .cloned()
+            .unwrap_or_default(),
+        &CsvColumn::Id =>
+            item.id.to_string(),
+        &CsvColumn::Type =>
+            type_name(item).to_owned(),
+        &CsvColumn::Lat =>
+            lat_lon
+            .map(|(lat, _)| format!("{:.7}", lat))
+            .unwrap_or_default(),
+        &CsvColumn::Lon =>
+            lat_lon
+            .map(|(_, lon)| format!("{:.7}", lon))
+            .unwrap_or_default(),
+        &CsvColumn::Length =>
+            locations
+            .map(|locations| format!("{:.1}", measure::length(item, locations)))
+            .unwrap_or_default(),
+        &CsvColumn::Area =>
+            locations
+            .map(|locations| format!("{:.1}", measure::area(item, locations)))
+            .unwrap_or_default(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific};
+    use set::Set;
+    use locations::Locations;
+    use ql::{OutputGeometry, CsvFormat, CsvColumn};
+    use writer::Writer;
+    use super::CsvWriter;
+
+    fn write(format: CsvFormat, set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> String {
+        let mut out = vec![];
+        {
+            let mut writer = CsvWriter::new(&mut out, format);
+            writer.begin().unwrap();
+            writer.write(set, geometry, locations).unwrap();
+            writer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut set = Set::empty();
+        let mut tags = HashMap::new();
+        tags.insert("name".to_owned(), "Zentralwerk, \"ZW\"".to_owned());
+        set.insert(Item::new(2, tags, ItemSpecific::Node { lat: 51.08, lon: 13.73 }));
+        set.insert(Item::new(1, HashMap::new(), ItemSpecific::Node { lat: 51.0, lon: 13.7 }));
+        set.insert(Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] }));
+        let format = CsvFormat {
+            columns: vec![CsvColumn::Id, CsvColumn::Type, CsvColumn::Lat, CsvColumn::Lon, CsvColumn::Tag("name".to_owned())],
+            header: true,
+            separator: ",".to_owned(),
+        };
+        assert_eq!(write(format.clone(), &set, &OutputGeometry::None, None), "@id,@type,@lat,@lon,name
+1,node,51.0000000,13.7000000,
+2,node,51.0800000,13.7300000,\"Zentralwerk, \"\"ZW\"\"\"
+10,way,,,
+");
+
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.7);
+        locations.insert_node(2, 51.08, 13.73);
+        let format = CsvFormat { header: false, separator: "\t".to_owned(), ..format };
+        assert!(write(format, &set, &OutputGeometry::Center, Some(&locations))
+                .ends_with("10\tway\t51.0400000\t13.7150000\t\n"));
+    }
+
+    #[test]
+    fn test_csv_length() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.0);
+        locations.insert_node(2, 52.0, 13.0);
+        let mut set = Set::empty();
+        set.insert(Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] }));
+        let format = CsvFormat {
+            columns: vec![CsvColumn::Length, CsvColumn::Area],
+            header: false,
+            separator: ",".to_owned(),
+        };
+        assert_eq!(write(format, &set, &OutputGeometry::None, Some(&locations)), "111194.9,0.0\n");
+    }
+}
@@ -0,0 +1,286 @@
+use std::borrow::Cow;
+use osm_pbf_iter::Primitive;
+
+use item::{Item, ItemSpecific, ItemId, ItemType};
+
+/// What filters evaluate. Implemented for the borrowed `Primitive`,
+/// so that only matching elements need to be allocated as `Item`.
+pub trait Element {
+    fn item_id(&self) -> ItemId;
+
+    fn tag(&self, key: &str) -> Option<&str>;
+
+    /// Does any tag satisfy `f`?
+    fn any_tag<F>(&self, f: F) -> bool
+    where
+        F: FnMut(&str, &str) -> bool;
+
+    fn get_lat_lon(&self) -> Option<(f64, f64)>;
+
+    /// Does any node of a way, or member of a relation, satisfy `f`?
+    fn any_member<F>(&self, f: F) -> bool
+    where
+        F: FnMut(&ItemId) -> bool;
+
+    /// Nodes of a way with an empty role, or members of a relation
+    /// with theirs, in order
+    fn for_each_member<F>(&self, f: F)
+    where
+        F: FnMut(&str, &ItemId);
+
+    /// Allocates, unless already an `Item`
+    fn to_item(&self) -> Cow<Item>;
+}
+
+impl Element for Item {
+    fn item_id(&self) -> ItemId {
+        Item::item_id(self)
+    }
+
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.tags.get(key)
+            .map(|value| value.as_str())
+    }
+
+    fn any_tag<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        self.tags.iter()
+            .any(|(k, v)| f(k, v))
+    }
+
+    fn get_lat_lon(&self) -> Option<(f64, f64)> {
+        Item::get_lat_lon(self)
+    }
+
+    fn any_member<F>(&self, f: F) -> bool
+    where
+        F: FnMut(&ItemId) -> bool,
+    {
+        Item::any_member(self, f)
+    }
+
+    fn for_each_member<F>(&self, mut f: F)
+    where
+        F: FnMut(&str, &ItemId),
+    {
+        match self.specific() {
+            &ItemSpecific::Way { ref refs } =>
+                for node_ref in refs {
+                    f("", &ItemId::new(ItemType::Node, *node_ref as u64));
+                },
+            &ItemSpecific::Relation { ref members } =>
+                for &(ref role, ref member) in members {
+                    f(role, member);
+                },
+            _ => {}
+        }
+    }
+
+    fn to_item(&self) -> Cow<Item> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl<'a> Element for Primitive<'a> {
+    fn item_id(&self) -> ItemId {
+        match self {
+            &Primitive::Node(ref node) =>
+                ItemId::new(ItemType::Node, node.id),
+            &Primitive::Way(ref way) =>
+                ItemId::new(ItemType::Way, way.id),
+            &Primitive::Relation(ref rel) =>
+                ItemId::new(ItemType::Relation, rel.id),
+        }
+    }
+
+    fn tag(&self, key: &str) -> Option<&str> {
+        match self {
+            &Primitive::Node(ref node) =>
+                node.tags.iter()
+                .find(|&&(k, _)| k == key)
+                .map(|&(_, v)| v),
+            &Primitive::Way(ref way) =>
+                way.tags()
+                .find(|&(k, _)| k == key)
+                .map(|(_, v)| v),
+            &Primitive::Relation(ref rel) =>
+                rel.tags()
+                .find(|&(k, _)| k == key)
+                .map(|(_, v)| v),
+        }
+    }
+
+    fn any_tag<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&str, &str) -> bool,
+    {
+        match self {
+            &Primitive::Node(ref node) =>
+                node.tags.iter()
+                .any(|&(k, v)| f(k, v)),
+            &Primitive::Way(ref way) =>
+                way.tags()
+                .any(|(k, v)| f(k, v)),
+            &Primitive::Relation(ref rel) =>
+                rel.tags()
+                .any(|(k, v)| f(k, v)),
+        }
+    }
+
+    fn get_lat_lon(&self) -> Option<(f64, f64)> {
+        match self {
+            &Primitive::Node(ref node) =>
+                Some((node.lat, node.lon)),
+            _ =>
+                None,
+        }
+    }
+
+    fn any_member<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&ItemId) -> bool,
+    {
+        match self {
+            &Primitive::Node(_) =>
+                false,
+            &Primitive::Way(ref way) =>
+                way.refs()
+                .any(|node_ref| f(&ItemId::new(ItemType::Node, node_ref as u64))),
+            &Primitive::Relation(ref rel) =>
+                rel.members()
+                .any(|(_, id, typ)| f(&ItemId::new(typ.into(), id))),
+        }
+    }
+
+    fn for_each_member<F>(&self, mut f: F)
+    where
+        F: FnMut(&str, &ItemId),
+    {
+        match self {
+            &Primitive::Node(_) => {}
+            &Primitive::Way(ref way) =>
+                for node_ref in way.refs() {
+                    f("", &ItemId::new(ItemType::Node, node_ref as u64));
+                },
+            &Primitive::Relation(ref rel) =>
+                for (role, id, typ) in rel.members() {
+                    f(role, &ItemId::new(typ.into(), id));
+                },
+        }
+    }
+
+    fn to_item(&self) -> Cow<Item> {
+        Cow::Owned(self.into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::collections::{HashMap, HashSet};
+    use osm_pbf_iter::{Primitive, PrimitiveBlock};
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use filter::{eval_filter, Context};
+    use recurse::is_recurse_match;
+    use pbf_writer::primitive_block;
+    use ql::{Filter, TagSpec, QueryType, SetName, RecurseType};
+    use super::Element;
+
+    fn tags(tags: &[(&str, &str)]) -> HashMap<String, String> {
+        tags.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    /// One block per type, as the PBF writer encodes them
+    fn blocks() -> Vec<Vec<u8>> {
+        vec![
+            primitive_block(&[
+                Item::new(1, tags(&[("name", "C3D2"), ("amenity", "hackerspace")]), ItemSpecific::Node { lat: 51.08, lon: 13.73 }),
+                Item::new(2, HashMap::new(), ItemSpecific::Node { lat: 51.09, lon: 13.74 }),
+            ]),
+            primitive_block(&[
+                Item::new(10, tags(&[("highway", "footway"), ("name", "Zentralwerk")]), ItemSpecific::Way { refs: vec![1, 2, 1] }),
+            ]),
+            primitive_block(&[
+                Item::new(20, tags(&[("type", "multipolygon")]), ItemSpecific::Relation {
+                    members: vec![
+                        ("outer".to_owned(), ItemId::new(ItemType::Way, 10)),
+                        ("".to_owned(), ItemId::new(ItemType::Node, 2)),
+                    ],
+                }),
+            ]),
+        ]
+    }
+
+    fn members<E: Element>(element: &E) -> Vec<(String, ItemId)> {
+        let mut members = vec![];
+        element.for_each_member(|role, member| members.push((role.to_owned(), *member)));
+        members
+    }
+
+    /// The borrowed `Primitive` must behave like the `Item` it
+    /// converts to
+    fn assert_same(primitive: &Primitive, filters: &[Filter], context: &Context) {
+        let item = Item::from(primitive);
+        assert_eq!(primitive.item_id(), item.item_id());
+        assert_eq!(primitive.get_lat_lon(), item.get_lat_lon());
+        assert_eq!(members(primitive), members(&item));
+        assert_eq!(primitive.to_item().into_owned(), item);
+        for filter in filters {
+            assert_eq!(
+                eval_filter(filter, primitive, context),
+                eval_filter(filter, &item, context),
+                "{:?} on {:?}", filter, item.item_id()
+            );
+        }
+        let ids = [ItemId::new(ItemType::Node, 2), ItemId::new(ItemType::Way, 10)].iter()
+            .cloned()
+            .collect::<HashSet<_>>();
+        for recurse_type in &[RecurseType::Up, RecurseType::Down] {
+            assert_eq!(
+                is_recurse_match(*recurse_type, &ids, primitive),
+                is_recurse_match(*recurse_type, &ids, &item)
+            );
+        }
+    }
+
+    #[test]
+    fn test_primitive_like_item() {
+        let mut set = Set::empty();
+        set.insert(Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![] }));
+        let mut context = Context::new();
+        context.add_set(SetName::from("a".to_owned()), Arc::new(set));
+        let filters = vec![
+            Filter::QueryType(QueryType::Node),
+            Filter::QueryType(QueryType::Way),
+            Filter::QueryType(QueryType::Relation),
+            Filter::QueryType(QueryType::NWR),
+            Filter::Id(1),
+            Filter::Id(10),
+            Filter::Id(20),
+            Filter::TagEqual { k: TagSpec::from_string("name"), v: TagSpec::from_string("C3D2") },
+            Filter::TagNotEqual { k: TagSpec::from_string("name"), v: TagSpec::from_string("C3D2") },
+            Filter::TagExist { k: TagSpec::from_string("name") },
+            Filter::TagNotExist { k: TagSpec::from_string("name") },
+            Filter::TagEqual { k: TagSpec::from_regex("^(high|rail)way$", false), v: TagSpec::from_regex("^FOOT", true) },
+            Filter::TagExist { k: TagSpec::from_regex("^ty", false) },
+            Filter::TagNotExist { k: TagSpec::from_regex("^ame", false) },
+            Filter::Intersection(SetName::from("a".to_owned())),
+        ];
+
+        let mut count = 0;
+        for data in blocks() {
+            let block = PrimitiveBlock::parse(&data);
+            for primitive in block.primitives() {
+                assert_same(&primitive, &filters, &context);
+                count += 1;
+            }
+        }
+        assert_eq!(count, 4);
+    }
+}
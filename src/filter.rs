@@ -1,42 +1,619 @@
-use item::Item;
+use std::sync::Arc;
+use std::collections::HashMap;
+
+use item::{Item, ItemSpecific, ItemType};
+use element::Element;
+use bbox::BoundingBox;
+use locations::Locations;
 use set::Set;
+use area::AreaStore;
+use geometry::MultiPolygon;
+use measure;
+use geodesic::{distance_to_line, lines_within};
 use pbf_source::PbfSource;
-use ql::{Statement, Filter, TagSpec, QueryType};
+use ql::{Statement, Filter, TagSpec, QueryType, SetName, Evaluator};
+
+/// What filters may refer to besides the item itself
+#[derive(Debug, Default, Clone)]
+pub struct Context {
+    /// Complete result sets of earlier passes, by the name they had
+    /// at the query statement
+    sets: HashMap<SetName, Arc<Set>>,
+    /// Collected in a pass before if any query `needs_locations()`
+    locations: Option<Arc<Locations>>,
+    /// Loaded if any process `needs_areas()`
+    areas: Option<Arc<AreaStore>>,
+    /// Members of the sets of `around.set` filters, by set name and
+    /// radius
+    around: Vec<(SetName, f64, Vec<Nearby>)>,
+}
+
+/// Lines of an `around.set` member, within a bounding box grown by
+/// the radius
+#[derive(Debug, Clone)]
+struct Nearby {
+    bbox: BoundingBox,
+    lines: Vec<Vec<(f64, f64)>>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context::default()
+    }
+
+    pub fn set_locations(&mut self, locations: Arc<Locations>) {
+        self.locations = Some(locations);
+    }
+
+    pub fn locations(&self) -> Option<&Locations> {
+        self.locations.as_ref()
+            .map(|locations| locations.as_ref())
+    }
+
+    pub fn set_areas(&mut self, areas: Arc<AreaStore>) {
+        self.areas = Some(areas);
+    }
 
-pub fn eval_filter(filter: &Filter, item: &Item) -> bool {
+    pub fn add_set(&mut self, name: SetName, set: Arc<Set>) {
+        self.sets.insert(name, set);
+    }
+
+    pub fn get_set(&self, name: &SetName) -> &Set {
+        self.sets.get(name)
+            .unwrap_or_else(|| panic!("No such set named {:?}", name))
+    }
+
+    /// Resolves the geometry of the `around.set` inputs once, instead
+    /// of for every element. Call after `add_set()`.
+    pub fn prepare_around(&mut self, filters: &[Filter]) {
+        for filter in filters {
+            if let &Filter::AroundSet { ref name, radius } = filter {
+                let members = self.get_set(name).iter()
+                    .filter_map(|member| {
+                        let lines = item_lines(member, self);
+                        let bbox = BoundingBox::planar_from_points(
+                            lines.iter().flat_map(|line| line.iter().cloned())
+                        )?;
+                        Some(Nearby { bbox: bbox.expand(radius), lines })
+                    }).collect();
+                self.around.push((name.clone(), radius, members));
+            }
+        }
+    }
+
+    fn around(&self, name: &SetName, radius: f64) -> &[Nearby] {
+        self.around.iter()
+            .find(|&&(ref around_name, around_radius, _)| around_name == name && around_radius == radius)
+            .map(|&(_, _, ref members)| members.as_slice())
+            .unwrap_or_else(|| panic!("around.{:?} was not prepared", name))
+    }
+}
+
+/// Generic over `Element` so that filters can be evaluated on
+/// primitives before allocating an `Item`
+pub fn eval_filter<E: Element>(filter: &Filter, item: &E, context: &Context) -> bool {
     match filter {
         &Filter::Id(id) =>
-            item.id == id,
-        &Filter::QueryType(query_type) =>
+            item.item_id().id == id,
+        &Filter::QueryType(query_type) => {
+            let item_type = item.item_id().item_type;
             match query_type {
                 QueryType::Node =>
-                    item.is_node(),
+                    item_type == ItemType::Node,
                 QueryType::Way =>
-                    item.is_way(),
+                    item_type == ItemType::Way,
                 QueryType::Relation =>
-                    item.is_relation(),
-                _ =>
-                    panic!("Not implemented"),
-            },
+                    item_type == ItemType::Relation,
+                QueryType::NWR =>
+                    item_type == ItemType::Node ||
+                    item_type == ItemType::Way ||
+                    item_type == ItemType::Relation,
+                QueryType::Area =>
+                    item_type == ItemType::Area,
+                QueryType::Derived =>
+                    item_type == ItemType::Derived,
+            }
+        }
         &Filter::BoundingBox { s, w, n, e } =>
-            item.get_lat_lon()
-            .map(
-                |(lat, lon)|
-                s <= lat && lat <= n &&
-                    w <= lon && lon <= e
-            ).unwrap_or(false),
+            in_bbox(item, &BoundingBox::new(s, w, n, e), context),
         &Filter::TagEqual { ref k, ref v } =>
-            match k {
-                TagSpec::String(s) =>
-                    item.tags.get(s)
-                    .map(|tv| v.test(tv))
+            has_tag_value(item, k, v),
+        // Also matches items without the key
+        &Filter::TagNotEqual { ref k, ref v } =>
+            ! has_tag_value(item, k, v),
+        &Filter::TagExist { ref k } =>
+            has_tag(item, k),
+        &Filter::TagNotExist { ref k } =>
+            ! has_tag(item, k),
+        &Filter::Intersection(ref name) =>
+            context.get_set(name)
+            .contains_id(&item.item_id()),
+        &Filter::Area(ref name) =>
+            context.get_set(name).iter()
+            .any(|area| in_area(item, area, context)),
+        &Filter::AreaId(id) =>
+            context.areas.as_ref()
+            .and_then(|areas| areas.get(id))
+            .map(|area| in_area(item, area, context))
+            .unwrap_or(false),
+        &Filter::Polygon(ref multipolygon) =>
+            in_multipolygon(item, multipolygon, context),
+        &Filter::If(ref evaluator) =>
+            evaluate(evaluator, item, context) != 0.0,
+        &Filter::Around { radius, ref line } =>
+            test_geometry(
+                item, context,
+                |point| distance_to_line(point, line) <= radius,
+                |points| lines_within(points, line, radius)
+            ),
+        &Filter::AroundSet { ref name, radius } => {
+            let nearby = context.around(name, radius);
+            test_geometry(
+                item, context,
+                |point| nearby.iter()
+                    .filter(|other| other.bbox.contains(point.0, point.1))
+                    .any(|other| other.lines.iter()
+                         .any(|line| distance_to_line(point, line) <= radius)),
+                |points| {
+                    let bbox = match BoundingBox::planar_from_points(points.iter().cloned()) {
+                        Some(bbox) => bbox,
+                        None => return false,
+                    };
+                    nearby.iter()
+                        .filter(|other| other.bbox.intersects(&bbox))
+                        .any(|other| other.lines.iter()
+                             .any(|line| lines_within(points, line, radius)))
+                }
+            )
+        }
+    }
+}
+
+/// Could the element match once locations and input sets are
+/// known? Only evaluates the filters that need neither.
+pub fn may_match<E: Element>(filters: &[Filter], item: &E) -> bool {
+    let context = Context::new();
+    filters.iter()
+        .all(|filter| match filter {
+            &Filter::Id(_) |
+            &Filter::QueryType(_) |
+            &Filter::TagEqual { .. } |
+            &Filter::TagNotEqual { .. } |
+            &Filter::TagExist { .. } |
+            &Filter::TagNotExist { .. } =>
+                eval_filter(filter, item, &context),
+            _ =>
+                true,
+        })
+}
+
+/// Do the filters test the geometry of ways or relations?
+pub fn needs_locations(filters: &[Filter]) -> bool {
+    let only_nodes = filters.contains(&Filter::QueryType(QueryType::Node));
+    let is_spatial = filters.iter()
+        .any(|filter| match filter {
+            &Filter::BoundingBox { .. } |
+            &Filter::Area(_) |
+            &Filter::AreaId(_) |
+            &Filter::Polygon(_) |
+            &Filter::Around { .. } => true,
+            &Filter::If(ref evaluator) => evaluator.needs_locations(),
+            _ => false,
+        });
+    // Input sets may contain ways and relations
+    let is_around_set = filters.iter()
+        .any(|filter| match filter {
+            &Filter::AroundSet { .. } => true,
+            _ => false,
+        });
+    (is_spatial && !only_nodes) || is_around_set
+}
+
+/// Does any filter refer to derived areas by id?
+pub fn needs_areas(filters: &[Filter]) -> bool {
+    filters.iter()
+        .any(|filter| match filter {
+            &Filter::QueryType(QueryType::Area) |
+            &Filter::AreaId(_) => true,
+            _ => false,
+        })
+}
+
+/// Measurements are zero without locations
+fn evaluate<E: Element>(evaluator: &Evaluator, item: &E, context: &Context) -> f64 {
+    let truth = |b| if b { 1.0 } else { 0.0 };
+    match evaluator {
+        &Evaluator::Number(n) =>
+            n,
+        &Evaluator::Length =>
+            context.locations()
+            .map(|locations| measure::length(item, locations))
+            .unwrap_or(0.0),
+        &Evaluator::Area =>
+            context.locations()
+            .map(|locations| measure::area(item, locations))
+            .unwrap_or(0.0),
+        &Evaluator::Compare(ref a, op, ref b) =>
+            truth(op.test(evaluate(a, item, context), evaluate(b, item, context))),
+        &Evaluator::And(ref a, ref b) =>
+            truth(evaluate(a, item, context) != 0.0 && evaluate(b, item, context) != 0.0),
+        &Evaluator::Or(ref a, ref b) =>
+            truth(evaluate(a, item, context) != 0.0 || evaluate(b, item, context) != 0.0),
+        &Evaluator::Not(ref a) =>
+            truth(evaluate(a, item, context) == 0.0),
+    }
+}
+
+fn in_bbox<E: Element>(item: &E, bbox: &BoundingBox, context: &Context) -> bool {
+    test_geometry(
+        item, context,
+        |(lat, lon)| bbox.contains(lat, lon),
+        |points| bbox.intersects_line(points.iter().cloned())
+    )
+}
+
+fn in_area<E: Element>(item: &E, area: &Item, context: &Context) -> bool {
+    match area.specific() {
+        &ItemSpecific::Area { ref multipolygon } =>
+            in_multipolygon(item, multipolygon, context),
+        _ =>
+            false,
+    }
+}
+
+fn in_multipolygon<E: Element>(item: &E, multipolygon: &MultiPolygon, context: &Context) -> bool {
+    let bbox = match multipolygon.bbox() {
+        Some(bbox) => bbox,
+        None => return false,
+    };
+    test_geometry(
+        item, context,
+        |point| bbox.contains(point.0, point.1) && multipolygon.contains(point),
+        |points| bbox.intersects_line(points.iter().cloned()) &&
+            multipolygon.intersects_line(points)
+    )
+}
+
+/// Points and polylines of a node, way, or the node and way members
+/// of a relation
+fn item_lines(item: &Item, context: &Context) -> Vec<Vec<(f64, f64)>> {
+    if let Some(point) = item.get_lat_lon() {
+        return vec![vec![point]];
+    }
+    let locations = match context.locations {
+        Some(ref locations) => locations,
+        None => return vec![],
+    };
+    match item.specific() {
+        &ItemSpecific::Way { ref refs } =>
+            vec![locations.ref_points(refs)],
+        &ItemSpecific::Relation { ref members } =>
+            members.iter()
+            .filter_map(|&(_, ref member)| match member.item_type {
+                ItemType::Node =>
+                    locations.node(member.id)
+                    .map(|point| vec![point]),
+                ItemType::Way =>
+                    locations.way_points(member.id),
+                _ =>
+                    None,
+            }).collect(),
+        _ =>
+            vec![],
+    }
+}
+
+/// Nodes must satisfy `test_point`, ways `test_line`, relations must
+/// have such a node or way as member.
+fn test_geometry<E, P, L>(item: &E, context: &Context, test_point: P, test_line: L) -> bool
+where
+    E: Element,
+    P: Fn((f64, f64)) -> bool,
+    L: Fn(&[(f64, f64)]) -> bool,
+{
+    if let Some(point) = item.get_lat_lon() {
+        return test_point(point);
+    }
+    let locations = match context.locations {
+        Some(ref locations) => locations,
+        None => return false,
+    };
+
+    let item_id = item.item_id();
+    match item_id.item_type {
+        ItemType::Way =>
+            locations.way_points(item_id.id)
+            .map(|points| test_line(&points))
+            .unwrap_or(false),
+        ItemType::Relation =>
+            item.any_member(|member| match member.item_type {
+                ItemType::Node =>
+                    locations.node(member.id)
+                    .map(|point| test_point(point))
+                    .unwrap_or(false),
+                ItemType::Way =>
+                    locations.way_points(member.id)
+                    .map(|points| test_line(&points))
                     .unwrap_or(false),
                 _ =>
-                    item.tags.iter()
-                    .any(
-                        |(tk, tv)| k.test(tk) && v.test(tv)
-                    ),
-            },
-        _ => panic!("Not implemented: {:?}", filter),
+                    false,
+            }),
+        _ =>
+            false,
+    }
+}
+
+fn has_tag<E: Element>(item: &E, k: &TagSpec) -> bool {
+    match k {
+        TagSpec::String(s) =>
+            item.tag(s).is_some(),
+        _ =>
+            item.any_tag(|tk, _| k.test(tk)),
+    }
+}
+
+fn has_tag_value<E: Element>(item: &E, k: &TagSpec, v: &TagSpec) -> bool {
+    match k {
+        TagSpec::String(s) =>
+            item.tag(s)
+            .map(|tv| v.test(tv))
+            .unwrap_or(false),
+        _ =>
+            item.any_tag(
+                |tk, tv| k.test(tk) && v.test(tv)
+            ),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use locations::Locations;
+    use geometry::{MultiPolygon, Polygon, Ring};
+    use ql::{Filter, TagSpec, SetName, QueryType, Evaluator, CompareOp};
+    use super::{eval_filter, may_match, needs_locations, Context};
+
+    fn node(tags: &[(&str, &str)]) -> Item {
+        let tags = tags.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect::<HashMap<_, _>>();
+        Item::new(1, tags, ItemSpecific::Node { lat: 0.0, lon: 0.0 })
+    }
+
+    #[test]
+    fn test_intersection() {
+        let mut set = Set::empty();
+        set.insert(node(&[]));
+        let mut context = Context::new();
+        context.add_set(SetName::from("a".to_owned()), Arc::new(set));
+
+        let filter = Filter::Intersection(SetName::from("a".to_owned()));
+        assert!(eval_filter(&filter, &node(&[]), &context));
+        let way = Item::new(1, HashMap::new(), ItemSpecific::Way { refs: vec![] });
+        assert!(!eval_filter(&filter, &way, &context));
+    }
+
+    #[test]
+    fn test_query_type_nwr() {
+        let filter = Filter::QueryType(QueryType::NWR);
+        let context = Context::new();
+        assert!(eval_filter(&filter, &node(&[]), &context));
+        let way = Item::new(1, HashMap::new(), ItemSpecific::Way { refs: vec![] });
+        assert!(eval_filter(&filter, &way, &context));
+        let relation = Item::new(1, HashMap::new(), ItemSpecific::Relation { members: vec![] });
+        assert!(eval_filter(&filter, &relation, &context));
+        let area = Item::new(2400000001, HashMap::new(), ItemSpecific::Area {
+            multipolygon: Arc::new(MultiPolygon::default()),
+        });
+        assert!(!eval_filter(&filter, &area, &context));
+    }
+
+    #[test]
+    fn test_query_type_area_derived() {
+        let context = Context::new();
+        let area = Item::new(2400000001, HashMap::new(), ItemSpecific::Area {
+            multipolygon: Arc::new(MultiPolygon::default()),
+        });
+        let derived = Item::new(1, HashMap::new(), ItemSpecific::Derived {
+            derived_type: "stat".to_owned(),
+        });
+        let area_filter = Filter::QueryType(QueryType::Area);
+        assert!(eval_filter(&area_filter, &area, &context));
+        assert!(!eval_filter(&area_filter, &derived, &context));
+        assert!(!eval_filter(&area_filter, &node(&[]), &context));
+        let derived_filter = Filter::QueryType(QueryType::Derived);
+        assert!(eval_filter(&derived_filter, &derived, &context));
+        assert!(!eval_filter(&derived_filter, &area, &context));
+    }
+
+    #[test]
+    fn test_bbox_way_and_relation() {
+        let mut locations = Locations::new();
+        // Outside, on both sides of the bbox
+        locations.insert_node(1, 51.5, 12.0);
+        locations.insert_node(2, 51.5, 15.0);
+        locations.insert_node(3, 50.0, 12.0);
+        locations.insert_way(10, vec![1, 2]);
+        locations.insert_way(11, vec![1, 3]);
+        let mut context = Context::new();
+        context.set_locations(Arc::new(locations));
+
+        let filter = Filter::BoundingBox { s: 51.0, w: 13.0, n: 52.0, e: 14.0 };
+        let crossing = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        assert!(eval_filter(&filter, &crossing, &context));
+        let outside = Item::new(11, HashMap::new(), ItemSpecific::Way { refs: vec![1, 3] });
+        assert!(!eval_filter(&filter, &outside, &context));
+        // Without locations
+        assert!(!eval_filter(&filter, &crossing, &Context::new()));
+
+        let relation = Item::new(20, HashMap::new(), ItemSpecific::Relation {
+            members: vec![
+                ("".to_owned(), ItemId::new(ItemType::Node, 3)),
+                ("".to_owned(), ItemId::new(ItemType::Way, 10)),
+            ],
+        });
+        assert!(eval_filter(&filter, &relation, &context));
+    }
+
+    #[test]
+    fn test_area() {
+        let square = Ring::new(vec![(51.0, 13.0), (51.0, 14.0), (52.0, 14.0), (52.0, 13.0), (51.0, 13.0)]);
+        let area = Item::new(3600000001, HashMap::new(), ItemSpecific::Area {
+            multipolygon: Arc::new(MultiPolygon::new(vec![
+                Polygon { outer: square, inners: vec![] },
+            ])),
+        });
+        let mut areas = Set::empty();
+        areas.insert(area);
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.5, 12.0);
+        locations.insert_node(2, 51.5, 15.0);
+        locations.insert_node(3, 50.0, 12.0);
+        locations.insert_way(10, vec![1, 2]);
+        locations.insert_way(11, vec![1, 3]);
+        let mut context = Context::new();
+        context.add_set(SetName::from("a".to_owned()), Arc::new(areas));
+        context.set_locations(Arc::new(locations));
+
+        let filter = Filter::Area(SetName::from("a".to_owned()));
+        let inside = Item::new(4, HashMap::new(), ItemSpecific::Node { lat: 51.5, lon: 13.5 });
+        assert!(eval_filter(&filter, &inside, &context));
+        assert!(!eval_filter(&filter, &node(&[]), &context));
+        let crossing = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        assert!(eval_filter(&filter, &crossing, &context));
+        let outside = Item::new(11, HashMap::new(), ItemSpecific::Way { refs: vec![1, 3] });
+        assert!(!eval_filter(&filter, &outside, &context));
+    }
+
+    #[test]
+    fn test_around() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.0);
+        locations.insert_node(2, 51.0, 14.0);
+        locations.insert_way(10, vec![1, 2]);
+        let mut context = Context::new();
+        context.set_locations(Arc::new(locations));
+
+        let near = Item::new(3, HashMap::new(), ItemSpecific::Node { lat: 51.0005, lon: 13.5 });
+        let far = Item::new(4, HashMap::new(), ItemSpecific::Node { lat: 51.002, lon: 13.5 });
+        let way = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+
+        let point = Filter::Around { radius: 100.0, line: vec![(51.0005, 13.5)] };
+        assert!(eval_filter(&point, &near, &context));
+        assert!(!eval_filter(&point, &far, &context));
+        assert!(eval_filter(&point, &way, &context));
+        let polyline = Filter::Around { radius: 100.0, line: vec![(51.0, 13.0), (51.0, 14.0)] };
+        assert!(eval_filter(&polyline, &near, &context));
+        assert!(!eval_filter(&polyline, &far, &context));
+
+        let mut set = Set::empty();
+        set.insert(way);
+        context.add_set(SetName::from("a".to_owned()), Arc::new(set));
+        let around_set = Filter::AroundSet { name: SetName::from("a".to_owned()), radius: 100.0 };
+        context.prepare_around(&[around_set.clone()]);
+        assert!(eval_filter(&around_set, &near, &context));
+        assert!(!eval_filter(&around_set, &far, &context));
+    }
+
+    #[test]
+    fn test_if() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.0);
+        locations.insert_node(2, 51.0, 13.01);
+        locations.insert_node(3, 51.0, 13.02);
+        let mut context = Context::new();
+        context.set_locations(Arc::new(locations));
+
+        let short = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        let long = Item::new(11, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2, 3] });
+        // 0.01° of longitude at 51°N are about 700m
+        let filter = Filter::If(Evaluator::Compare(
+            Box::new(Evaluator::Length),
+            CompareOp::Greater,
+            Box::new(Evaluator::Number(1000.0))
+        ));
+        assert!(!eval_filter(&filter, &short, &context));
+        assert!(eval_filter(&filter, &long, &context));
+        assert!(!eval_filter(&Filter::If(Evaluator::Area), &long, &context));
+    }
+
+    #[test]
+    fn test_needs_locations() {
+        let bbox = Filter::BoundingBox { s: 51.0, w: 13.0, n: 52.0, e: 14.0 };
+        assert!(needs_locations(&[Filter::QueryType(QueryType::Way), bbox.clone()]));
+        assert!(!needs_locations(&[Filter::QueryType(QueryType::Node), bbox.clone()]));
+        assert!(!needs_locations(&[Filter::QueryType(QueryType::Way)]));
+        assert!(needs_locations(&[Filter::QueryType(QueryType::NWR), Filter::AreaId(3600062422)]));
+        let count = Evaluator::Not(Box::new(Evaluator::Number(0.0)));
+        assert!(!needs_locations(&[Filter::QueryType(QueryType::Way), Filter::If(count)]));
+        let long = Evaluator::Compare(Box::new(Evaluator::Length), CompareOp::Greater, Box::new(Evaluator::Number(1000.0)));
+        assert!(needs_locations(&[Filter::QueryType(QueryType::Way), Filter::If(long)]));
+    }
+
+    #[test]
+    fn test_may_match() {
+        let way = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        let bbox = Filter::BoundingBox { s: 51.0, w: 13.0, n: 52.0, e: 14.0 };
+        assert!(may_match(&[Filter::QueryType(QueryType::Way), bbox.clone()], &way));
+        assert!(!may_match(&[Filter::QueryType(QueryType::Node), bbox], &way));
+        assert!(!may_match(&[Filter::TagExist { k: TagSpec::from_string("name") }], &way));
+        assert!(may_match(&[Filter::Intersection(SetName::from("a".to_owned()))], &way));
+    }
+
+    #[test]
+    fn test_tag_exist() {
+        let filter = Filter::TagExist { k: TagSpec::from_string("name") };
+        assert!(eval_filter(&filter, &node(&[("name", "C3D2")]), &Context::new()));
+        assert!(!eval_filter(&filter, &node(&[("name:en", "C3D2")]), &Context::new()));
+        assert!(!eval_filter(&filter, &node(&[]), &Context::new()));
+    }
+
+    #[test]
+    fn test_tag_exist_regex() {
+        let filter = Filter::TagExist { k: TagSpec::from_regex("^addr:", false) };
+        assert!(eval_filter(&filter, &node(&[("addr:street", "Zur Kleinen Hopfenhalle")]), &Context::new()));
+        assert!(!eval_filter(&filter, &node(&[("name", "C3D2")]), &Context::new()));
+    }
+
+    #[test]
+    fn test_tag_not_exist() {
+        let filter = Filter::TagNotExist { k: TagSpec::from_string("name") };
+        assert!(!eval_filter(&filter, &node(&[("name", "C3D2")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[("leisure", "hackerspace")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[]), &Context::new()));
+    }
+
+    #[test]
+    fn test_tag_not_exist_regex() {
+        let filter = Filter::TagNotExist { k: TagSpec::from_regex("addr", true) };
+        assert!(!eval_filter(&filter, &node(&[("ADDR:city", "Dresden")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[("name", "C3D2")]), &Context::new()));
+    }
+
+    #[test]
+    fn test_tag_not_equal() {
+        let filter = Filter::TagNotEqual {
+            k: TagSpec::from_string("access"),
+            v: TagSpec::from_string("private"),
+        };
+        assert!(!eval_filter(&filter, &node(&[("access", "private")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[("access", "yes")]), &Context::new()));
+        // Overpass matches elements without the key
+        assert!(eval_filter(&filter, &node(&[]), &Context::new()));
+    }
+
+    #[test]
+    fn test_tag_not_equal_regex() {
+        let filter = Filter::TagNotEqual {
+            k: TagSpec::from_regex("^name", false),
+            v: TagSpec::from_regex("space", true),
+        };
+        assert!(!eval_filter(&filter, &node(&[("name:de", "Hackerspace")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[("name", "C3D2")]), &Context::new()));
+        assert!(eval_filter(&filter, &node(&[("leisure", "hackerspace")]), &Context::new()));
     }
 }
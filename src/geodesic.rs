@@ -0,0 +1,125 @@
+use geometry::segments_intersect;
+
+/// Mean earth radius in meters, as used by Overpass
+pub const EARTH_RADIUS: f64 = 6371000.0;
+
+/// Great-circle distance in meters (haversine)
+pub fn distance((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) +
+        lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Length in meters along the points
+pub fn line_length(points: &[(f64, f64)]) -> f64 {
+    points.windows(2)
+        .map(|segment| distance(segment[0], segment[1]))
+        .sum()
+}
+
+/// Area in square meters enclosed by a ring on the sphere,
+/// regardless of its orientation
+pub fn ring_area(points: &[(f64, f64)]) -> f64 {
+    let sum: f64 = points.windows(2)
+        .map(|segment| {
+            let (lat1, lon1) = segment[0];
+            let (lat2, lon2) = segment[1];
+            (lon2 - lon1).to_radians() *
+                (2.0 + lat1.to_radians().sin() + lat2.to_radians().sin())
+        }).sum();
+    (sum * EARTH_RADIUS * EARTH_RADIUS / 2.0).abs()
+}
+
+/// Distance in meters from `p` to the closest point of the segment
+/// a-b. The closest point is found on a plane tangent at `p`, which
+/// is good for the short distances of `around`.
+pub fn distance_to_segment(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let scale = p.0.to_radians().cos();
+    let (ax, ay) = ((a.1 - p.1) * scale, a.0 - p.0);
+    let (bx, by) = ((b.1 - p.1) * scale, b.0 - p.0);
+    let (dx, dy) = (bx - ax, by - ay);
+    let len2 = dx * dx + dy * dy;
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        (-(ax * dx + ay * dy) / len2).clamp(0.0, 1.0)
+    };
+    let closest = (a.0 + t * (b.0 - a.0), a.1 + t * (b.1 - a.1));
+    distance(p, closest)
+}
+
+/// Distance in meters from `p` to a single point or a polyline
+pub fn distance_to_line(p: (f64, f64), line: &[(f64, f64)]) -> f64 {
+    match line.len() {
+        0 => f64::INFINITY,
+        1 => distance(p, line[0]),
+        _ =>
+            line.windows(2)
+            .map(|segment| distance_to_segment(p, segment[0], segment[1]))
+            .fold(f64::INFINITY, f64::min),
+    }
+}
+
+/// Do two polylines (or points) come within `radius` meters?
+pub fn lines_within(a: &[(f64, f64)], b: &[(f64, f64)], radius: f64) -> bool {
+    a.iter().any(|p| distance_to_line(*p, b) <= radius) ||
+        b.iter().any(|p| distance_to_line(*p, a) <= radius) ||
+        a.windows(2).any(|sa|
+            b.windows(2).any(|sb| segments_intersect(sa[0], sa[1], sb[0], sb[1]))
+        )
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{distance, distance_to_line, lines_within, line_length, ring_area};
+
+    #[test]
+    fn test_distance() {
+        // One degree of latitude
+        let d = distance((51.0, 13.0), (52.0, 13.0));
+        assert!((d - 111195.0).abs() < 1.0);
+        // Dresden to Leipzig
+        let d = distance((51.0504, 13.7373), (51.3397, 12.3731));
+        assert!((d - 100000.0).abs() < 2000.0);
+    }
+
+    #[test]
+    fn test_line_length() {
+        let d = line_length(&[(51.0, 13.0), (52.0, 13.0), (52.0, 13.0)]);
+        assert!((d - 111195.0).abs() < 1.0);
+        assert_eq!(line_length(&[(51.0, 13.0)]), 0.0);
+    }
+
+    #[test]
+    fn test_ring_area() {
+        // One degree square at the equator, about 111.2km x 111.2km
+        let square = [(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        let area = ring_area(&square);
+        assert!((area - 1.2364e10).abs() < 1e7);
+        let mut reversed = square.to_vec();
+        reversed.reverse();
+        assert!((ring_area(&reversed) - area).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_distance_to_line() {
+        let line = [(51.0, 13.0), (51.0, 14.0)];
+        // 0.001° north of the middle is about 111m
+        let d = distance_to_line((51.001, 13.5), &line);
+        assert!((d - 111.2).abs() < 0.5);
+        // Beyond the end, the end point is closest
+        assert_eq!(distance_to_line((51.0, 14.5), &line), distance((51.0, 14.5), (51.0, 14.0)));
+    }
+
+    #[test]
+    fn test_lines_within() {
+        let a = [(51.0, 13.0), (51.0, 14.0)];
+        assert!(lines_within(&a, &[(51.0005, 13.5)], 100.0));
+        assert!(!lines_within(&a, &[(51.002, 13.5)], 100.0));
+        // Crossing without any vertex nearby
+        assert!(lines_within(&a, &[(50.0, 13.5), (52.0, 13.5)], 1.0));
+    }
+}
@@ -0,0 +1,234 @@
+use std::io::{self, Write};
+
+use item::{Item, ItemSpecific, ItemType};
+use set::Set;
+use locations::Locations;
+use geometry::{assemble_area, Ring, Polygon, MultiPolygon};
+use area::has_area_tags;
+use ql::OutputGeometry;
+use json;
+use writer::{Writer, sorted_items, sorted_tags, type_name};
+
+/// GeoJSON `FeatureCollection`, `[out:geojson]`. Ways and relations
+/// always get their full geometry, whatever the `out` mode.
+pub struct GeoJsonWriter<W: Write> {
+    out: W,
+    /// Features of all `out` statements share one array
+    first: bool,
+}
+
+impl<W: Write> GeoJsonWriter<W> {
+    pub fn new(out: W) -> Self {
+        GeoJsonWriter {
+            out,
+            first: true,
+        }
+    }
+}
+
+impl<W: Write> Writer for GeoJsonWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        writeln!(self.out, "{{")?;
+        writeln!(self.out, "  \"type\": \"FeatureCollection\",")?;
+        write!(self.out, "  \"features\": [")
+    }
+
+    fn write(&mut self, set: &Set, _geometry: &OutputGeometry, locations: Option<&Locations>) -> io::Result<()> {
+        let locations = locations
+            .expect("GeoJSON output without locations");
+        for item in sorted_items(set) {
+            let separator = if self.first { "" } else { "," };
+            self.first = false;
+            write!(self.out, "{}\n    {}", separator, feature(item, locations))?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        writeln!(self.out, "\n  ]")?;
+        writeln!(self.out, "}}")?;
+        self.out.flush()
+    }
+
+    fn needs_locations(&self) -> bool {
+        true
+    }
+}
+
+/// Tags become properties. The id is prefixed with the type, like
+/// osmtogeojson does.
+fn feature(item: &Item, locations: &Locations) -> String {
+    let properties = sorted_tags(item).into_iter()
+        .map(|(k, v)| format!("{}: {}", json::string(k), json::string(v)))
+        .collect::<Vec<_>>();
+    format!(
+        "{{ \"type\": \"Feature\", \"id\": {}, \"properties\": {{ {} }}, \"geometry\": {} }}",
+        json::string(&format!("{}/{}", type_name(item), item.id)),
+        properties.join(", "),
+        geometry(item, locations).unwrap_or_else(|| "null".to_owned())
+    )
+}
+
+/// `None` when there are not enough known points
+fn geometry(item: &Item, locations: &Locations) -> Option<String> {
+    match item.specific() {
+        &ItemSpecific::Node { lat, lon } =>
+            Some(format!("{{ \"type\": \"Point\", \"coordinates\": {} }}", position((lat, lon)))),
+        &ItemSpecific::Way { .. } if item.is_area() && has_area_tags(item) =>
+            assemble_area(item, locations)
+            .and_then(|assembly| assembly.multipolygon.polygons.into_iter().next())
+            .map(|polygon| format!(
+                "{{ \"type\": \"Polygon\", \"coordinates\": {} }}",
+                polygon_coordinates(&polygon)
+            )),
+        &ItemSpecific::Way { ref refs } =>
+            line_string(&locations.ref_points(refs)),
+        &ItemSpecific::Relation { ref members } =>
+            match assemble_area(item, locations) {
+                Some(ref assembly) if !assembly.multipolygon.is_empty() =>
+                    Some(multipolygon(&assembly.multipolygon)),
+                _ => {
+                    // Points and lines of the members
+                    let geometries = members.iter()
+                        .filter_map(|&(_, ref member)| match member.item_type {
+                            ItemType::Node =>
+                                locations.node(member.id)
+                                .map(|point| format!(
+                                    "{{ \"type\": \"Point\", \"coordinates\": {} }}",
+                                    position(point)
+                                )),
+                            ItemType::Way =>
+                                locations.way_points(member.id)
+                                .and_then(|points| line_string(&points)),
+                            _ =>
+                                None,
+                        }).collect::<Vec<_>>();
+                    if geometries.is_empty() {
+                        None
+                    } else {
+                        Some(format!(
+                            "{{ \"type\": \"GeometryCollection\", \"geometries\": [{}] }}",
+                            geometries.join(", ")
+                        ))
+                    }
+                }
+            },
+        &ItemSpecific::Area { ref multipolygon } if !multipolygon.is_empty() =>
+            Some(self::multipolygon(multipolygon)),
+        _ =>
+            None,
+    }
+}
+
+fn line_string(points: &[(f64, f64)]) -> Option<String> {
+    if points.len() < 2 {
+        return None;
+    }
+    Some(format!("{{ \"type\": \"LineString\", \"coordinates\": {} }}", positions(points)))
+}
+
+fn multipolygon(multipolygon: &MultiPolygon) -> String {
+    let polygons = multipolygon.polygons.iter()
+        .map(polygon_coordinates)
+        .collect::<Vec<_>>();
+    format!("{{ \"type\": \"MultiPolygon\", \"coordinates\": [{}] }}", polygons.join(", "))
+}
+
+/// Outer rings counter-clockwise, holes clockwise, as RFC 7946
+/// recommends
+fn polygon_coordinates(polygon: &Polygon) -> String {
+    let mut rings = vec![ring_coordinates(&polygon.outer, true)];
+    rings.extend(polygon.inners.iter().map(|inner| ring_coordinates(inner, false)));
+    format!("[{}]", rings.join(", "))
+}
+
+fn ring_coordinates(ring: &Ring, counter_clockwise: bool) -> String {
+    if (ring.signed_area() > 0.0) == counter_clockwise {
+        positions(&ring.points)
+    } else {
+        let mut points = ring.points.clone();
+        points.reverse();
+        positions(&points)
+    }
+}
+
+fn positions(points: &[(f64, f64)]) -> String {
+    let points = points.iter()
+        .map(|point| position(*point))
+        .collect::<Vec<_>>();
+    format!("[{}]", points.join(", "))
+}
+
+/// GeoJSON has longitude first
+fn position((lat, lon): (f64, f64)) -> String {
+    format!("[{:.7}, {:.7}]", lon, lat)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use locations::Locations;
+    use super::feature;
+
+    fn tags(tags: &[(&str, &str)]) -> HashMap<String, String> {
+        tags.iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    /// A clockwise square with a clockwise hole
+    fn locations() -> Locations {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 0.0, 0.0);
+        locations.insert_node(2, 1.0, 0.0);
+        locations.insert_node(3, 1.0, 1.0);
+        locations.insert_node(4, 0.0, 1.0);
+        locations.insert_way(10, vec![1, 2, 3, 4, 1]);
+        locations.insert_node(5, 0.25, 0.25);
+        locations.insert_node(6, 0.75, 0.25);
+        locations.insert_node(7, 0.75, 0.75);
+        locations.insert_node(8, 0.25, 0.75);
+        locations.insert_way(11, vec![5, 6, 7, 8, 5]);
+        locations
+    }
+
+    #[test]
+    fn test_point() {
+        let node = Item::new(1, tags(&[("name", "C3D2")]), ItemSpecific::Node { lat: 51.0, lon: 13.7 });
+        assert_eq!(feature(&node, &Locations::new()), r#"{ "type": "Feature", "id": "node/1", "properties": { "name": "C3D2" }, "geometry": { "type": "Point", "coordinates": [13.7000000, 51.0000000] } }"#);
+    }
+
+    #[test]
+    fn test_ways() {
+        let locations = locations();
+        let line = Item::new(12, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        assert!(feature(&line, &locations).ends_with(r#""geometry": { "type": "LineString", "coordinates": [[0.0000000, 0.0000000], [0.0000000, 1.0000000]] } }"#));
+        // Closed, but without area tags
+        let closed_line = Item::new(10, tags(&[("highway", "pedestrian")]), ItemSpecific::Way { refs: vec![1, 2, 3, 4, 1] });
+        assert!(feature(&closed_line, &locations).contains(r#""type": "LineString""#));
+        let area = Item::new(10, tags(&[("building", "yes")]), ItemSpecific::Way { refs: vec![1, 2, 3, 4, 1] });
+        assert!(feature(&area, &locations).ends_with(r#""geometry": { "type": "Polygon", "coordinates": [[[0.0000000, 0.0000000], [1.0000000, 0.0000000], [1.0000000, 1.0000000], [0.0000000, 1.0000000], [0.0000000, 0.0000000]]] } }"#));
+        let missing = Item::new(13, HashMap::new(), ItemSpecific::Way { refs: vec![1, 99] });
+        assert!(feature(&missing, &locations).ends_with(r#""geometry": null }"#));
+    }
+
+    #[test]
+    fn test_multipolygon() {
+        let relation = Item::new(20, tags(&[("type", "multipolygon")]), ItemSpecific::Relation {
+            members: vec![
+                ("outer".to_owned(), ItemId::new(ItemType::Way, 10)),
+                ("inner".to_owned(), ItemId::new(ItemType::Way, 11)),
+            ],
+        });
+        assert!(feature(&relation, &locations()).ends_with(r#""geometry": { "type": "MultiPolygon", "coordinates": [[[[0.0000000, 0.0000000], [1.0000000, 0.0000000], [1.0000000, 1.0000000], [0.0000000, 1.0000000], [0.0000000, 0.0000000]], [[0.2500000, 0.2500000], [0.2500000, 0.7500000], [0.7500000, 0.7500000], [0.7500000, 0.2500000], [0.2500000, 0.2500000]]]] } }"#));
+        let route = Item::new(21, tags(&[("type", "route")]), ItemSpecific::Relation {
+            members: vec![
+                ("".to_owned(), ItemId::new(ItemType::Node, 5)),
+                ("".to_owned(), ItemId::new(ItemType::Way, 11)),
+            ],
+        });
+        assert!(feature(&route, &locations()).contains(r#""geometry": { "type": "GeometryCollection", "geometries": [{ "type": "Point""#));
+    }
+}
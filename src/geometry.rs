@@ -0,0 +1,421 @@
+use item::ItemType;
+use element::Element;
+use locations::Locations;
+use bbox::BoundingBox;
+
+/// Closed sequence of (lat, lon): the first point equals the last
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ring {
+    pub points: Vec<(f64, f64)>,
+}
+
+impl Ring {
+    pub fn new(points: Vec<(f64, f64)>) -> Self {
+        Ring { points }
+    }
+
+    /// Ray casting on the plane spanned by longitude and latitude
+    pub fn contains(&self, (lat, lon): (f64, f64)) -> bool {
+        let points = &self.points;
+        if points.is_empty() {
+            return false;
+        }
+
+        let mut inside = false;
+        let mut j = points.len() - 1;
+        for i in 0..points.len() {
+            let (lat_i, lon_i) = points[i];
+            let (lat_j, lon_j) = points[j];
+            if (lat_i > lat) != (lat_j > lat) &&
+                lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+
+    /// Does any edge cross the segment from `a` to `b`?
+    pub fn crosses_segment(&self, a: (f64, f64), b: (f64, f64)) -> bool {
+        self.points.windows(2)
+            .any(|edge| segments_intersect(edge[0], edge[1], a, b))
+    }
+
+    /// Planar area in square degrees, positive for counter-clockwise
+    /// rings. Only good for comparisons.
+    pub fn signed_area(&self) -> f64 {
+        let points = &self.points;
+        let mut sum = 0.0;
+        for i in 1..points.len() {
+            let (lat1, lon1) = points[i - 1];
+            let (lat2, lon2) = points[i];
+            sum += lon1 * lat2 - lon2 * lat1;
+        }
+        sum / 2.0
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Polygon {
+    pub outer: Ring,
+    pub inners: Vec<Ring>,
+}
+
+impl Polygon {
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        self.outer.contains(point) &&
+            !self.inners.iter()
+            .any(|inner| inner.contains(point))
+    }
+}
+
+/// Construct with `new()`, which computes the bounding box
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct MultiPolygon {
+    pub polygons: Vec<Polygon>,
+    /// Computed once, as filters check it for every element
+    bbox: Option<BoundingBox>,
+}
+
+impl MultiPolygon {
+    pub fn new(polygons: Vec<Polygon>) -> Self {
+        let bbox = BoundingBox::planar_from_points(
+            polygons.iter()
+                .flat_map(|polygon| polygon.outer.points.iter().cloned())
+        );
+        MultiPolygon { polygons, bbox }
+    }
+
+    pub fn contains(&self, point: (f64, f64)) -> bool {
+        self.polygons.iter()
+            .any(|polygon| polygon.contains(point))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// Is any point inside, or does any segment cross a ring?
+    pub fn intersects_line(&self, points: &[(f64, f64)]) -> bool {
+        let rings = || self.polygons.iter()
+            .flat_map(|polygon| Some(&polygon.outer).into_iter().chain(polygon.inners.iter()));
+        points.iter().any(|point| self.contains(*point)) ||
+            points.windows(2).any(|segment|
+                rings().any(|ring| ring.crosses_segment(segment[0], segment[1]))
+            )
+    }
+
+    /// Bounds of the outer rings, on the plane like `contains()`
+    pub fn bbox(&self) -> Option<BoundingBox> {
+        self.bbox
+    }
+}
+
+/// Do the segments a-b and c-d touch or cross?
+pub fn segments_intersect(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    fn orientation(p: (f64, f64), q: (f64, f64), r: (f64, f64)) -> f64 {
+        (q.1 - p.1) * (r.0 - p.0) - (q.0 - p.0) * (r.1 - p.1)
+    }
+    fn on_segment(p: (f64, f64), q: (f64, f64), r: (f64, f64)) -> bool {
+        r.0 >= p.0.min(q.0) && r.0 <= p.0.max(q.0) &&
+            r.1 >= p.1.min(q.1) && r.1 <= p.1.max(q.1)
+    }
+
+    let d1 = orientation(c, d, a);
+    let d2 = orientation(c, d, b);
+    let d3 = orientation(a, b, c);
+    let d4 = orientation(a, b, d);
+    if ((d1 > 0.0 && d2 < 0.0) || (d1 < 0.0 && d2 > 0.0)) &&
+        ((d3 > 0.0 && d4 < 0.0) || (d3 < 0.0 && d4 > 0.0)) {
+        return true;
+    }
+    (d1 == 0.0 && on_segment(c, d, a)) ||
+        (d2 == 0.0 && on_segment(c, d, b)) ||
+        (d3 == 0.0 && on_segment(a, b, c)) ||
+        (d4 == 0.0 && on_segment(a, b, d))
+}
+
+/// Reasons for rings left out of an assembled `MultiPolygon`. Way ids
+/// identify the parts of a ring.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GeometryError {
+    /// Member way not in the data
+    MissingWay(u64),
+    /// Ring of these ways, some of whose nodes are not in the data
+    MissingNodes(Vec<u64>),
+    /// Ways that could not be joined into a closed ring
+    UnclosedRing(Vec<u64>),
+    /// Closed ring with less than three distinct points
+    DegenerateRing(Vec<u64>),
+    /// Inner ring that lies in no outer ring
+    InnerWithoutOuter(Vec<u64>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assembly {
+    pub multipolygon: MultiPolygon,
+    /// Broken parts, not contained in `multipolygon`
+    pub errors: Vec<GeometryError>,
+}
+
+/// Area geometry of closed ways and of `type=multipolygon` and
+/// `type=boundary` relations. `None` for any other element.
+pub fn assemble_area<E: Element>(item: &E, locations: &Locations) -> Option<Assembly> {
+    let item_id = item.item_id();
+    match item_id.item_type {
+        ItemType::Way => {
+            let mut refs = vec![];
+            item.for_each_member(|_, node| refs.push(node.id as i64));
+            if refs.is_empty() || refs.first() != refs.last() {
+                return None;
+            }
+            let segment = Segment {
+                ways: vec![item_id.id],
+                refs,
+            };
+            Some(assemble(vec![segment], vec![], vec![], locations))
+        }
+        ItemType::Relation if is_multipolygon(item) => {
+            let mut outers = vec![];
+            let mut inners = vec![];
+            let mut errors = vec![];
+            item.for_each_member(|role, member| {
+                if member.item_type != ItemType::Way {
+                    return;
+                }
+                let segments = match role {
+                    // Untagged members are outer by convention
+                    "outer" | "" => &mut outers,
+                    "inner" => &mut inners,
+                    _ => return,
+                };
+                match locations.way_refs(member.id) {
+                    Some(refs) =>
+                        segments.push(Segment {
+                            ways: vec![member.id],
+                            refs: refs.to_vec(),
+                        }),
+                    None =>
+                        errors.push(GeometryError::MissingWay(member.id)),
+                }
+            });
+            Some(assemble(outers, inners, errors, locations))
+        }
+        _ =>
+            None,
+    }
+}
+
+fn is_multipolygon<E: Element>(item: &E) -> bool {
+    match item.tag("type") {
+        Some("multipolygon") | Some("boundary") =>
+            true,
+        _ =>
+            false,
+    }
+}
+
+fn assemble(outers: Vec<Segment>, inners: Vec<Segment>, mut errors: Vec<GeometryError>, locations: &Locations) -> Assembly {
+    let outer_rings = build_rings(outers, locations, &mut errors);
+    let inner_rings = build_rings(inners, locations, &mut errors);
+
+    let mut polygons = outer_rings.into_iter()
+        .map(|(_, outer)| Polygon { outer, inners: vec![] })
+        .collect::<Vec<_>>();
+    for (ways, inner) in inner_rings {
+        // The smallest outer ring containing it, for nested polygons
+        let point = inner.points[0];
+        let polygon = polygons.iter_mut()
+            .filter(|polygon| polygon.outer.contains(point))
+            .min_by(|a, b| {
+                let a = a.outer.signed_area().abs();
+                let b = b.outer.signed_area().abs();
+                a.partial_cmp(&b).unwrap()
+            });
+        match polygon {
+            Some(polygon) =>
+                polygon.inners.push(inner),
+            None =>
+                errors.push(GeometryError::InnerWithoutOuter(ways)),
+        }
+    }
+
+    Assembly {
+        multipolygon: MultiPolygon::new(polygons),
+        errors,
+    }
+}
+
+/// Node refs of a way or of joined ways
+struct Segment {
+    ways: Vec<u64>,
+    refs: Vec<i64>,
+}
+
+impl Segment {
+    fn is_closed(&self) -> bool {
+        self.refs.len() > 1 &&
+            self.refs[0] == self.refs[self.refs.len() - 1]
+    }
+
+    /// Append `other` if it starts or ends where `self` ends
+    fn join(&mut self, mut other: Segment) {
+        if other.refs.first() != self.refs.last() {
+            other.refs.reverse();
+        }
+        self.refs.extend(other.refs.into_iter().skip(1));
+        self.ways.extend(other.ways);
+    }
+}
+
+/// Join segments end to end into closed rings, with the ids of their
+/// ways
+fn build_rings(mut segments: Vec<Segment>, locations: &Locations, errors: &mut Vec<GeometryError>) -> Vec<(Vec<u64>, Ring)> {
+    segments.retain(|segment| !segment.refs.is_empty());
+
+    let mut rings = vec![];
+    while let Some(mut current) = segments.pop() {
+        let mut reversed = false;
+        while !current.is_closed() {
+            let end = current.refs[current.refs.len() - 1];
+            let next = segments.iter()
+                .position(|segment|
+                          segment.refs[0] == end ||
+                          segment.refs[segment.refs.len() - 1] == end
+                );
+            match next {
+                Some(i) =>
+                    current.join(segments.swap_remove(i)),
+                None if !reversed => {
+                    // Try to continue at the other end
+                    current.refs.reverse();
+                    reversed = true;
+                }
+                None =>
+                    break,
+            }
+        }
+        if !current.is_closed() {
+            errors.push(GeometryError::UnclosedRing(current.ways));
+            continue;
+        }
+
+        let points = locations.ref_points(&current.refs);
+        if points.len() < current.refs.len() {
+            errors.push(GeometryError::MissingNodes(current.ways));
+        } else if points.len() < 4 {
+            errors.push(GeometryError::DegenerateRing(current.ways));
+        } else {
+            rings.push((current.ways, Ring::new(points)));
+        }
+    }
+    rings
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use locations::Locations;
+    use super::{assemble_area, GeometryError, Ring};
+
+    /// A 4x4 square of nodes 1..4, split into two ways 10 and 11,
+    /// with a 2x2 square 5..8 (closed way 12) in the middle.
+    fn locations() -> Locations {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 0.0, 0.0);
+        locations.insert_node(2, 0.0, 4.0);
+        locations.insert_node(3, 4.0, 4.0);
+        locations.insert_node(4, 4.0, 0.0);
+        locations.insert_way(10, vec![1, 2, 3]);
+        locations.insert_way(11, vec![1, 4, 3]);
+        locations.insert_node(5, 1.0, 1.0);
+        locations.insert_node(6, 1.0, 3.0);
+        locations.insert_node(7, 3.0, 3.0);
+        locations.insert_node(8, 3.0, 1.0);
+        locations.insert_way(12, vec![5, 6, 7, 8, 5]);
+        locations.insert_way(13, vec![6, 7]);
+        locations
+    }
+
+    fn multipolygon(members: &[(&str, u64)]) -> Item {
+        let mut tags = HashMap::new();
+        tags.insert("type".to_owned(), "multipolygon".to_owned());
+        Item::new(100, tags, ItemSpecific::Relation {
+            members: members.iter()
+                .map(|&(role, id)| (role.to_owned(), ItemId::new(ItemType::Way, id)))
+                .collect(),
+        })
+    }
+
+    #[test]
+    fn test_ring_contains() {
+        let ring = Ring::new(vec![(0.0, 0.0), (0.0, 4.0), (4.0, 4.0), (4.0, 0.0), (0.0, 0.0)]);
+        assert!(ring.contains((2.0, 2.0)));
+        assert!(!ring.contains((5.0, 2.0)));
+        assert!(!ring.contains((-1.0, -1.0)));
+    }
+
+    #[test]
+    fn test_intersects_line() {
+        let relation = multipolygon(&[("outer", 10), ("outer", 11), ("inner", 12)]);
+        let multipolygon = assemble_area(&relation, &locations()).unwrap().multipolygon;
+        // Point inside
+        assert!(multipolygon.intersects_line(&[(0.5, 0.5)]));
+        // Crossing the outer ring from outside
+        assert!(multipolygon.intersects_line(&[(-1.0, 2.0), (5.0, 2.0)]));
+        // Within the hole, touching no ring
+        assert!(!multipolygon.intersects_line(&[(1.5, 1.5), (2.5, 2.5)]));
+        // Passing by
+        assert!(!multipolygon.intersects_line(&[(-1.0, -1.0), (-1.0, 5.0)]));
+    }
+
+    #[test]
+    fn test_closed_way() {
+        let way = Item::new(12, HashMap::new(), ItemSpecific::Way {
+            refs: vec![5, 6, 7, 8, 5],
+        });
+        let assembly = assemble_area(&way, &locations()).unwrap();
+        assert_eq!(assembly.errors, vec![]);
+        assert_eq!(assembly.multipolygon.polygons.len(), 1);
+        assert!(assembly.multipolygon.contains((2.0, 2.0)));
+
+        let open_way = Item::new(10, HashMap::new(), ItemSpecific::Way {
+            refs: vec![1, 2, 3],
+        });
+        assert!(assemble_area(&open_way, &locations()).is_none());
+    }
+
+    #[test]
+    fn test_joined_outer_with_inner() {
+        let relation = multipolygon(&[("outer", 10), ("outer", 11), ("inner", 12)]);
+        let assembly = assemble_area(&relation, &locations()).unwrap();
+        assert_eq!(assembly.errors, vec![]);
+        let polygons = &assembly.multipolygon.polygons;
+        assert_eq!(polygons.len(), 1);
+        assert_eq!(polygons[0].outer.points.len(), 5);
+        assert_eq!(polygons[0].inners.len(), 1);
+        assert!(assembly.multipolygon.contains((0.5, 0.5)));
+        assert!(!assembly.multipolygon.contains((2.0, 2.0)));
+    }
+
+    #[test]
+    fn test_broken_rings() {
+        let relation = multipolygon(&[("outer", 10), ("outer", 13), ("inner", 14)]);
+        let assembly = assemble_area(&relation, &locations()).unwrap();
+        assert!(assembly.multipolygon.is_empty());
+        assert_eq!(assembly.errors.len(), 3);
+        assert!(assembly.errors.contains(&GeometryError::MissingWay(14)));
+        assert!(assembly.errors.contains(&GeometryError::UnclosedRing(vec![13])));
+        assert!(assembly.errors.contains(&GeometryError::UnclosedRing(vec![10])));
+    }
+
+    #[test]
+    fn test_inner_without_outer() {
+        let relation = multipolygon(&[("inner", 12)]);
+        let assembly = assemble_area(&relation, &locations()).unwrap();
+        assert!(assembly.multipolygon.is_empty());
+        assert_eq!(assembly.errors, vec![GeometryError::InnerWithoutOuter(vec![12])]);
+    }
+}
@@ -1,7 +1,44 @@
 use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use std::collections::HashMap;
 use osm_pbf_iter::{Primitive, Node, Way, Relation, RelationMemberType};
 
+use geometry::MultiPolygon;
+
+/// Element ids are only unique per type
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub enum ItemType {
+    Node,
+    Way,
+    Relation,
+    Area,
+    Derived,
+}
+
+impl From<RelationMemberType> for ItemType {
+    fn from(member_type: RelationMemberType) -> Self {
+        match member_type {
+            RelationMemberType::Node => ItemType::Node,
+            RelationMemberType::Way => ItemType::Way,
+            RelationMemberType::Relation => ItemType::Relation,
+        }
+    }
+}
+
+/// Identifies an element across types. Ordered by type, then id,
+/// like Overpass output.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy)]
+pub struct ItemId {
+    pub item_type: ItemType,
+    pub id: u64,
+}
+
+impl ItemId {
+    pub fn new(item_type: ItemType, id: u64) -> Self {
+        ItemId { item_type, id }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Item {
     pub id: u64,
@@ -10,7 +47,7 @@ pub struct Item {
     specific: ItemSpecific,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ItemSpecific {
     Node {
         lat: f64,
@@ -20,11 +57,44 @@ pub enum ItemSpecific {
         refs: Vec<i64>,
     },
     Relation {
-        members: Vec<(String, u64, RelationMemberType)>,
+        /// (role, member)
+        members: Vec<(String, ItemId)>,
+    },
+    /// Derived from a closed way or a multipolygon relation
+    Area {
+        multipolygon: Arc<MultiPolygon>,
+    },
+    /// Produced by make/convert
+    // Constructed once make/convert exist
+    #[allow(dead_code)]
+    Derived {
+        derived_type: String,
     },
 }
 
 impl Item {
+    pub fn new(id: u64, tags: HashMap<String, String>, specific: ItemSpecific) -> Self {
+        Item { id, tags, specific }
+    }
+
+    pub fn item_type(&self) -> ItemType {
+        match self.specific {
+            ItemSpecific::Node { .. } => ItemType::Node,
+            ItemSpecific::Way { .. } => ItemType::Way,
+            ItemSpecific::Relation { .. } => ItemType::Relation,
+            ItemSpecific::Area { .. } => ItemType::Area,
+            ItemSpecific::Derived { .. } => ItemType::Derived,
+        }
+    }
+
+    pub fn item_id(&self) -> ItemId {
+        ItemId::new(self.item_type(), self.id)
+    }
+
+    pub fn specific(&self) -> &ItemSpecific {
+        &self.specific
+    }
+
     pub fn is_node(&self) -> bool {
         match self.specific {
             ItemSpecific::Node { .. } => true,
@@ -67,11 +137,43 @@ impl Item {
         }
     }
 
+    /// Nodes of a way, or members of a relation
+    pub fn member_ids(&self) -> Vec<ItemId> {
+        match self.specific {
+            ItemSpecific::Way { ref refs } =>
+                refs.iter()
+                .map(|node_ref| ItemId::new(ItemType::Node, *node_ref as u64))
+                .collect(),
+            ItemSpecific::Relation { ref members } =>
+                members.iter()
+                .map(|&(_, member)| member)
+                .collect(),
+            _ =>
+                vec![],
+        }
+    }
+
+    /// Like `member_ids()` but without allocation
+    pub fn any_member<F>(&self, mut f: F) -> bool
+    where
+        F: FnMut(&ItemId) -> bool,
+    {
+        match self.specific {
+            ItemSpecific::Way { ref refs } =>
+                refs.iter()
+                .any(|node_ref| f(&ItemId::new(ItemType::Node, *node_ref as u64))),
+            ItemSpecific::Relation { ref members } =>
+                members.iter()
+                .any(|&(_, ref member)| f(member)),
+            _ =>
+                false,
+        }
+    }
 }
 
 impl PartialEq for Item {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        self.item_id() == other.item_id()
     }
 }
 impl Eq for Item {
@@ -82,25 +184,31 @@ impl<'a> Hash for Item {
     where
         H: Hasher,
     {
-        self.id.hash(state)
+        self.item_id().hash(state)
     }
 }
 
 impl<'a> From<Primitive<'a>> for Item {
     fn from(primitive: Primitive<'a>) -> Self {
+        (&primitive).into()
+    }
+}
+
+impl<'a, 'p> From<&'p Primitive<'a>> for Item {
+    fn from(primitive: &'p Primitive<'a>) -> Self {
         match primitive {
-            Primitive::Node(node) =>
+            &Primitive::Node(ref node) =>
                 node.into(),
-            Primitive::Relation(rel) =>
+            &Primitive::Relation(ref rel) =>
                 rel.into(),
-            Primitive::Way(way) =>
+            &Primitive::Way(ref way) =>
                 way.into(),
         }
     }
 }
 
-impl<'a> From<Node<'a>> for Item {
-    fn from(node: Node<'a>) -> Self {
+impl<'a, 'p> From<&'p Node<'a>> for Item {
+    fn from(node: &'p Node<'a>) -> Self {
         Item {
             id: node.id,
             tags: node.tags.iter()
@@ -115,8 +223,8 @@ impl<'a> From<Node<'a>> for Item {
     }
 }
 
-impl<'a> From<Way<'a>> for Item {
-    fn from(way: Way<'a>) -> Self {
+impl<'a, 'p> From<&'p Way<'a>> for Item {
+    fn from(way: &'p Way<'a>) -> Self {
         Item {
             id: way.id,
             tags: way.tags()
@@ -130,8 +238,8 @@ impl<'a> From<Way<'a>> for Item {
     }
 }
 
-impl<'a> From<Relation<'a>> for Item {
-    fn from(rel: Relation<'a>) -> Self {
+impl<'a, 'p> From<&'p Relation<'a>> for Item {
+    fn from(rel: &'p Relation<'a>) -> Self {
         Item {
             id: rel.id,
             tags: rel.tags()
@@ -141,7 +249,7 @@ impl<'a> From<Relation<'a>> for Item {
             specific: ItemSpecific::Relation {
                 members: rel.members()
                     .map(
-                        |(role, id, typ)| (role.to_string(), id, typ)
+                        |(role, id, typ)| (role.to_string(), ItemId::new(typ.into(), id))
                     ).collect(),
             },
         }
@@ -0,0 +1,277 @@
+use std::io::{self, Write};
+
+use item::{Item, ItemSpecific, ItemType};
+use set::Set;
+use bbox::BoundingBox;
+use locations::Locations;
+use ql::OutputGeometry;
+use output::{self, Geometry};
+use writer::{Writer, sorted_items, sorted_tags, type_name, item_type_name};
+
+const COPYRIGHT: &str = "The data included in this document is from www.openstreetmap.org. The data is made available under ODbL.";
+
+/// Overpass JSON, `[out:json]`
+pub struct JsonWriter<W: Write> {
+    out: W,
+    /// Replication timestamp of the data, in seconds since the epoch
+    timestamp: Option<i64>,
+    /// Elements of all `out` statements share one array
+    first: bool,
+}
+
+impl<W: Write> JsonWriter<W> {
+    pub fn new(out: W, timestamp: Option<i64>) -> Self {
+        JsonWriter {
+            out,
+            timestamp,
+            first: true,
+        }
+    }
+}
+
+impl<W: Write> Writer for JsonWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        writeln!(self.out, "{{")?;
+        writeln!(self.out, "  \"version\": 0.6,")?;
+        writeln!(self.out, "  \"generator\": \"Underpass Turbo\",")?;
+        let timestamp = self.timestamp
+            .map(iso8601)
+            .unwrap_or_default();
+        writeln!(self.out, "  \"osm3s\": {{")?;
+        writeln!(self.out, "    \"timestamp_osm_base\": {},", string(&timestamp))?;
+        writeln!(self.out, "    \"copyright\": {}", string(COPYRIGHT))?;
+        writeln!(self.out, "  }},")?;
+        write!(self.out, "  \"elements\": [")
+    }
+
+    fn write(&mut self, set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> io::Result<()> {
+        for item in sorted_items(set) {
+            let item_geometry = locations
+                .and_then(|locations| output::resolve(item, geometry, locations));
+            let separator = if self.first { "" } else { "," };
+            self.first = false;
+            write!(self.out, "{}\n    {}", separator, element(item, item_geometry))?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        writeln!(self.out, "\n  ]")?;
+        writeln!(self.out, "}}")?;
+        self.out.flush()
+    }
+}
+
+/// One element object on a single line
+fn element(item: &Item, geometry: Option<Geometry>) -> String {
+    let mut fields = vec![
+        format!("\"type\": {}", string(type_name(item))),
+        format!("\"id\": {}", item.id),
+    ];
+    if let Some((lat, lon)) = item.get_lat_lon() {
+        fields.push(format!("\"lat\": {:.7}", lat));
+        fields.push(format!("\"lon\": {:.7}", lon));
+    }
+    match geometry {
+        Some(Geometry::Center(lat, lon)) =>
+            fields.push(format!("\"center\": {}", point(Some((lat, lon))))),
+        Some(Geometry::Bounds(ref bounds)) |
+        Some(Geometry::Line { ref bounds, .. }) |
+        Some(Geometry::Members { bounds: Some(ref bounds), .. }) =>
+            fields.push(format!("\"bounds\": {}", bounds_object(bounds))),
+        _ => {}
+    }
+    match item.specific() {
+        &ItemSpecific::Way { ref refs } => {
+            let refs = refs.iter()
+                .map(|node_ref| node_ref.to_string())
+                .collect::<Vec<_>>();
+            fields.push(format!("\"nodes\": [{}]", refs.join(", ")));
+            if let Some(Geometry::Line { ref points, .. }) = geometry {
+                fields.push(format!("\"geometry\": {}", points_array(points)));
+            }
+        }
+        &ItemSpecific::Relation { ref members } => {
+            let member_points = match geometry {
+                Some(Geometry::Members { ref members, .. }) => Some(members),
+                _ => None,
+            };
+            let members = members.iter()
+                .enumerate()
+                .map(|(i, &(ref role, ref member))| {
+                    let mut member_fields = vec![
+                        format!("\"type\": {}", string(item_type_name(member.item_type))),
+                        format!("\"ref\": {}", member.id),
+                        format!("\"role\": {}", string(role)),
+                    ];
+                    match member_points.map(|member_points| &member_points[i]) {
+                        Some(points) if member.item_type == ItemType::Node && points.len() == 1 => {
+                            if let Some((lat, lon)) = points[0] {
+                                member_fields.push(format!("\"lat\": {:.7}", lat));
+                                member_fields.push(format!("\"lon\": {:.7}", lon));
+                            }
+                        }
+                        Some(points) if !points.is_empty() =>
+                            member_fields.push(format!("\"geometry\": {}", points_array(points))),
+                        _ => {}
+                    }
+                    format!("{{ {} }}", member_fields.join(", "))
+                }).collect::<Vec<_>>();
+            fields.push(format!("\"members\": [{}]", members.join(", ")));
+        }
+        _ => {}
+    }
+    let tags = sorted_tags(item);
+    if !tags.is_empty() {
+        let tags = tags.into_iter()
+            .map(|(k, v)| format!("{}: {}", string(k), string(v)))
+            .collect::<Vec<_>>();
+        fields.push(format!("\"tags\": {{ {} }}", tags.join(", ")));
+    }
+    format!("{{ {} }}", fields.join(", "))
+}
+
+fn bounds_object(bounds: &BoundingBox) -> String {
+    format!(
+        "{{ \"minlat\": {:.7}, \"minlon\": {:.7}, \"maxlat\": {:.7}, \"maxlon\": {:.7} }}",
+        bounds.s, bounds.w, bounds.n, bounds.e
+    )
+}
+
+/// Missing and clipped points are `null`
+fn point(point: Option<(f64, f64)>) -> String {
+    match point {
+        Some((lat, lon)) => format!("{{ \"lat\": {:.7}, \"lon\": {:.7} }}", lat, lon),
+        None => "null".to_owned(),
+    }
+}
+
+fn points_array(points: &[Option<(f64, f64)>]) -> String {
+    let points = points.iter()
+        .map(|p| point(*p))
+        .collect::<Vec<_>>();
+    format!("[{}]", points.join(", "))
+}
+
+/// `2017-03-01T12:00:00Z`, like Overpass timestamps
+fn iso8601(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86400);
+    let seconds = timestamp.rem_euclid(86400);
+    // Civil date from days since 1970-01-01, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60
+    )
+}
+
+/// Quoted and escaped JSON string
+pub fn string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use locations::Locations;
+    use ql::OutputGeometry;
+    use writer::Writer;
+    use super::{JsonWriter, string, iso8601};
+
+    fn write(sets: &[Set], geometry: &OutputGeometry, locations: Option<&Locations>) -> String {
+        let mut out = vec![];
+        {
+            let mut writer = JsonWriter::new(&mut out, None);
+            writer.begin().unwrap();
+            for set in sets {
+                writer.write(set, geometry, locations).unwrap();
+            }
+            writer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\"\\\n\u{1}"), r#""a \"b\"\\\n\u0001""#);
+    }
+
+    #[test]
+    fn test_iso8601() {
+        assert_eq!(iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso8601(1700000000), "2023-11-14T22:13:20Z");
+        assert_eq!(iso8601(951782400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn test_json() {
+        let mut nodes = Set::empty();
+        let mut tags = HashMap::new();
+        tags.insert("name".to_owned(), "C3D2".to_owned());
+        nodes.insert(Item::new(1, tags, ItemSpecific::Node { lat: 51.0, lon: 13.7 }));
+        let mut relations = Set::empty();
+        relations.insert(Item::new(20, HashMap::new(), ItemSpecific::Relation {
+            members: vec![("".to_owned(), ItemId::new(ItemType::Node, 1))],
+        }));
+        assert_eq!(write(&[nodes, relations], &OutputGeometry::None, None), r#"{
+  "version": 0.6,
+  "generator": "Underpass Turbo",
+  "osm3s": {
+    "timestamp_osm_base": "",
+    "copyright": "The data included in this document is from www.openstreetmap.org. The data is made available under ODbL."
+  },
+  "elements": [
+    { "type": "node", "id": 1, "lat": 51.0000000, "lon": 13.7000000, "tags": { "name": "C3D2" } },
+    { "type": "relation", "id": 20, "members": [{ "type": "node", "ref": 1, "role": "" }] }
+  ]
+}
+"#);
+        assert_eq!(write(&[], &OutputGeometry::None, None), r#"{
+  "version": 0.6,
+  "generator": "Underpass Turbo",
+  "osm3s": {
+    "timestamp_osm_base": "",
+    "copyright": "The data included in this document is from www.openstreetmap.org. The data is made available under ODbL."
+  },
+  "elements": [
+  ]
+}
+"#);
+    }
+
+    #[test]
+    fn test_json_geom() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.7);
+        locations.insert_node(2, 51.1, 13.8);
+        let mut ways = Set::empty();
+        ways.insert(Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2, 3] }));
+        let output = write(&[ways], &OutputGeometry::Geom { clip: None }, Some(&locations));
+        assert!(output.contains(r#"{ "type": "way", "id": 10, "bounds": { "minlat": 51.0000000, "minlon": 13.7000000, "maxlat": 51.1000000, "maxlon": 13.8000000 }, "nodes": [1, 2, 3], "geometry": [{ "lat": 51.0000000, "lon": 13.7000000 }, { "lat": 51.1000000, "lon": 13.8000000 }, null] }"#));
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use osm_pbf_iter::Primitive;
+
+use item::ItemType;
+use process::Worker;
+
+/// Which ways and relations need their geometry
+pub type IsCandidate = Arc<dyn Fn(&Primitive) -> bool + Send + Sync>;
+
+/// Node coordinates and way refs, to resolve the geometry of ways
+/// and relations
+#[derive(Debug, Default)]
+pub struct Locations {
+    nodes: HashMap<u64, (f64, f64)>,
+    ways: HashMap<u64, Vec<i64>>,
+}
+
+impl Locations {
+    pub fn new() -> Self {
+        Locations::default()
+    }
+
+    pub fn merge<I>(mut locations: I) -> Self
+    where
+        I: Iterator<Item=Self>,
+    {
+        let mut result = locations.next()
+            .unwrap_or_else(Locations::new);
+        for other in locations {
+            result.nodes.extend(other.nodes);
+            result.ways.extend(other.ways);
+        }
+        result
+    }
+
+    pub fn insert_node(&mut self, id: u64, lat: f64, lon: f64) {
+        self.nodes.insert(id, (lat, lon));
+    }
+
+    pub fn insert_way(&mut self, id: u64, refs: Vec<i64>) {
+        self.ways.insert(id, refs);
+    }
+
+    /// (lat, lon)
+    pub fn node(&self, id: u64) -> Option<(f64, f64)> {
+        self.nodes.get(&id)
+            .cloned()
+    }
+
+    pub fn way_refs(&self, id: u64) -> Option<&[i64]> {
+        self.ways.get(&id)
+            .map(|refs| &refs[..])
+    }
+
+    /// Coordinates of a way, skipping missing nodes
+    pub fn way_points(&self, id: u64) -> Option<Vec<(f64, f64)>> {
+        self.way_refs(id)
+            .map(|refs| self.ref_points(refs))
+    }
+
+    /// Coordinates of node refs, skipping missing nodes
+    pub fn ref_points(&self, refs: &[i64]) -> Vec<(f64, f64)> {
+        refs.iter()
+            .filter_map(|node_ref| self.node(*node_ref as u64))
+            .collect()
+    }
+}
+
+impl Worker for Locations {
+    fn process(&mut self, primitive: &Primitive) {
+        match primitive {
+            &Primitive::Node(ref node) =>
+                self.insert_node(node.id, node.lat, node.lon),
+            &Primitive::Way(ref way) =>
+                self.insert_way(way.id, way.refs().collect()),
+            &Primitive::Relation(_) =>
+                (),
+        }
+    }
+}
+
+/// Scan worker collecting the refs of candidate ways, and which
+/// nodes and ways the candidates reference
+pub struct References {
+    is_candidate: IsCandidate,
+    pub locations: Locations,
+    pub nodes: HashSet<u64>,
+    pub ways: HashSet<u64>,
+}
+
+impl References {
+    pub fn new(is_candidate: IsCandidate) -> Self {
+        References {
+            is_candidate,
+            locations: Locations::new(),
+            nodes: HashSet::new(),
+            ways: HashSet::new(),
+        }
+    }
+}
+
+impl Worker for References {
+    fn process(&mut self, primitive: &Primitive) {
+        match primitive {
+            &Primitive::Way(ref way) if (self.is_candidate)(primitive) => {
+                let refs = way.refs().collect::<Vec<_>>();
+                self.nodes.extend(refs.iter().map(|node_ref| *node_ref as u64));
+                self.locations.insert_way(way.id, refs);
+            }
+            &Primitive::Relation(ref rel) if (self.is_candidate)(primitive) =>
+                for (_, id, member_type) in rel.members() {
+                    match ItemType::from(member_type) {
+                        ItemType::Node => {
+                            self.nodes.insert(id);
+                        }
+                        ItemType::Way => {
+                            self.ways.insert(id);
+                        }
+                        _ =>
+                            (),
+                    }
+                },
+            _ =>
+                (),
+        }
+    }
+}
+
+/// Scan worker collecting the locations of the wanted nodes only
+pub struct WantedNodes {
+    wanted: Arc<HashSet<u64>>,
+    pub locations: Locations,
+}
+
+impl WantedNodes {
+    pub fn new(wanted: Arc<HashSet<u64>>) -> Self {
+        WantedNodes {
+            wanted,
+            locations: Locations::new(),
+        }
+    }
+}
+
+impl Worker for WantedNodes {
+    fn process(&mut self, primitive: &Primitive) {
+        if let &Primitive::Node(ref node) = primitive {
+            if self.wanted.contains(&node.id) {
+                self.locations.insert_node(node.id, node.lat, node.lon);
+            }
+        }
+    }
+}
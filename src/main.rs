@@ -4,18 +4,28 @@ extern crate threadpool;
 extern crate regex;
 #[macro_use] extern crate lalrpop_util;
 
-use clap::{Arg, App};
+use std::io;
+
+use clap::{Arg, App, AppSettings, SubCommand};
 
 mod ql;
-use ql::{Statement, Filter, TagSpec, QueryType};
 
 mod item;
+mod element;
 mod set;
 mod pbf_source;
 use pbf_source::PbfSource;
 mod process;
 use process::Runner;
 mod filter;
+mod bbox;
+mod locations;
+mod binary;
+mod geometry;
+mod geodesic;
+mod measure;
+mod poly;
+mod area;
 mod trace;
 use trace::trace;
 mod trace_node;
@@ -23,11 +33,36 @@ mod process_node;
 mod planner;
 use planner::plan;
 mod query;
+mod recurse;
+mod output;
+mod writer;
+use writer::Writer;
+mod xml;
+use xml::XmlWriter;
+mod json;
+use json::JsonWriter;
+mod geojson;
+use geojson::GeoJsonWriter;
+mod csv;
+use csv::CsvWriter;
+mod pbf_writer;
+use pbf_writer::PbfWriter;
+mod spatial_index;
+use spatial_index::SpatialIndex;
 
 fn main() {
     let matches = App::new("Underpass Turbo")
         .version("0.1.0")
         .author("Astro <astro@spaceboyz.net>")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(SubCommand::with_name("index")
+             .about("Build spatial indexes next to the PBF files")
+             .arg(Arg::with_name("PBF")
+                  .help("OpenStreetMap dump files (one or more)")
+                  .required(true)
+                  .multiple(true)
+             )
+        )
         .arg(Arg::with_name("QUERY")
              .help("QL source")
              .required(true)
@@ -37,38 +72,60 @@ fn main() {
              .help("OpenStreetMap dump files (one or more)")
              .required(true)
              .multiple(true)
+        )
+        .arg(Arg::with_name("poly")
+             .long("poly")
+             .value_name("FILE")
+             .help("Restrict all queries to an osmosis .poly file")
+             .takes_value(true)
         ).get_matches();
+
+    if let Some(matches) = matches.subcommand_matches("index") {
+        let source_paths = matches.values_of_os("PBF")
+            .expect("Source paths missing");
+        let runner = Runner::new(PbfSource::new(source_paths));
+        SpatialIndex::build(&runner)
+            .save()
+            .expect("Cannot save index");
+        return;
+    }
+
     let query = matches.value_of("QUERY")
         .expect("Query missing");
-    let script = ql::parse(query);
-    println!("parsed query: {:?}", script);
+    let (settings, mut script) = ql::parse_script(query);
+    if let Some(path) = matches.value_of("poly") {
+        let multipolygon = poly::read_poly_file(path)
+            .expect("Cannot read polygon file");
+        let filter = ql::Filter::Polygon(multipolygon);
+        for statement_spec in script.iter_mut() {
+            statement_spec.add_filter(&filter);
+        }
+    }
+    eprintln!("parsed query: {:?}", script);
     let script_trace = trace::trace(script.into_iter());
-    println!("traced query: {:?}", script_trace);
-    plan(&script_trace);
+    eprintln!("traced query: {:?}", script_trace);
+    let plan = plan(&script_trace);
 
     let source_paths = matches.values_of_os("PBF")
         .expect("Source paths missing");
     let source = PbfSource::new(source_paths);
-    let runner = Runner::new(source);
-
-    let statement = Statement::Query {
-        filters: vec![
-            // Filter::QueryType(QueryType::Node),
-            // Filter::Id(2331619771),
-            // Filter::BoundingBox {
-            //     // 51.0810832, 13.7286525
-            //     s: 51.0810,
-            //     n: 51.0811,
-            //     w: 13.7286,
-            //     e: 13.7287,
-            // },
-            // Filter::Id(372193022),
-            Filter::TagEqual {
-                k: TagSpec::from_string("leisure"),
-                v: TagSpec::from_regex("space", true),
-            }
-        ],
+    let timestamp = source.replication_timestamp();
+    let index = SpatialIndex::open(&source);
+    let mut runner = Runner::new(source);
+    match index {
+        Some(index) =>
+            runner.set_index(index),
+        None =>
+            eprintln!("No spatial index, scanning all data"),
+    }
+    let stdout = io::stdout();
+    let out = io::BufWriter::new(stdout.lock());
+    let mut writer: Box<dyn Writer> = match settings.output_format {
+        ql::OutputFormat::Xml => Box::new(XmlWriter::new(out)),
+        ql::OutputFormat::Json => Box::new(JsonWriter::new(out, timestamp)),
+        ql::OutputFormat::GeoJson => Box::new(GeoJsonWriter::new(out)),
+        ql::OutputFormat::Csv(format) => Box::new(CsvWriter::new(out, format)),
+        ql::OutputFormat::Pbf => Box::new(PbfWriter::new(out)),
     };
-    // let r = runner.run_all(statement);
-    // println!("{:?}", r);
+    plan.run(&runner, &mut *writer);
 }
@@ -0,0 +1,111 @@
+use item::{ItemSpecific, ItemType};
+use element::Element;
+use locations::Locations;
+use geometry::{assemble_area, MultiPolygon};
+use geodesic::{line_length, ring_area};
+
+/// Geodesic length in meters of a way, or of all way members of a
+/// relation. Zero for anything else.
+pub fn length<E: Element>(item: &E, locations: &Locations) -> f64 {
+    match item.item_id().item_type {
+        ItemType::Way => {
+            let mut points = vec![];
+            item.for_each_member(|_, node| points.extend(locations.node(node.id)));
+            line_length(&points)
+        }
+        ItemType::Relation => {
+            let mut length = 0.0;
+            item.for_each_member(|_, member| {
+                if member.item_type == ItemType::Way {
+                    if let Some(points) = locations.way_points(member.id) {
+                        length += line_length(&points);
+                    }
+                }
+            });
+            length
+        }
+        _ =>
+            0.0,
+    }
+}
+
+/// Geodesic area in square meters of closed ways, multipolygons and
+/// derived areas. Zero for anything else.
+pub fn area<E: Element>(item: &E, locations: &Locations) -> f64 {
+    match item.item_id().item_type {
+        // Only ever an `Item`, so this borrows
+        ItemType::Area =>
+            match item.to_item().specific() {
+                &ItemSpecific::Area { ref multipolygon } =>
+                    multipolygon_area(multipolygon),
+                _ =>
+                    0.0,
+            },
+        ItemType::Way | ItemType::Relation =>
+            assemble_area(item, locations)
+            .map(|assembly| multipolygon_area(&assembly.multipolygon))
+            .unwrap_or(0.0),
+        _ =>
+            0.0,
+    }
+}
+
+fn multipolygon_area(multipolygon: &MultiPolygon) -> f64 {
+    multipolygon.polygons.iter()
+        .map(|polygon| {
+            let holes: f64 = polygon.inners.iter()
+                .map(|inner| ring_area(&inner.points))
+                .sum();
+            ring_area(&polygon.outer.points) - holes
+        }).sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use locations::Locations;
+    use super::{length, area};
+
+    /// A square of 0.01° at the equator, about 1113m per side, with
+    /// a hole of a quarter its size
+    fn locations() -> Locations {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 0.0, 0.0);
+        locations.insert_node(2, 0.0, 0.01);
+        locations.insert_node(3, 0.01, 0.01);
+        locations.insert_node(4, 0.01, 0.0);
+        locations.insert_way(10, vec![1, 2, 3, 4, 1]);
+        locations.insert_node(5, 0.0025, 0.0025);
+        locations.insert_node(6, 0.0025, 0.0075);
+        locations.insert_node(7, 0.0075, 0.0075);
+        locations.insert_node(8, 0.0075, 0.0025);
+        locations.insert_way(11, vec![5, 6, 7, 8, 5]);
+        locations
+    }
+
+    #[test]
+    fn test_way() {
+        let way = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2, 3, 4, 1] });
+        assert!((length(&way, &locations()) - 4.0 * 1111.95).abs() < 1.0);
+        assert!((area(&way, &locations()) - 1111.95 * 1111.95).abs() < 100.0);
+
+        let open_way = Item::new(12, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] });
+        assert_eq!(area(&open_way, &locations()), 0.0);
+    }
+
+    #[test]
+    fn test_multipolygon() {
+        let mut tags = HashMap::new();
+        tags.insert("type".to_owned(), "multipolygon".to_owned());
+        let relation = Item::new(20, tags, ItemSpecific::Relation {
+            members: vec![
+                ("outer".to_owned(), ItemId::new(ItemType::Way, 10)),
+                ("inner".to_owned(), ItemId::new(ItemType::Way, 11)),
+            ],
+        });
+        assert!((length(&relation, &locations()) - 6.0 * 1111.95).abs() < 1.0);
+        assert!((area(&relation, &locations()) - 0.75 * 1111.95 * 1111.95).abs() < 100.0);
+    }
+}
@@ -0,0 +1,209 @@
+use item::{Item, ItemSpecific, ItemType};
+use bbox::BoundingBox;
+use locations::Locations;
+use ql::OutputGeometry;
+
+/// Coordinates of a way or relation, resolved for `out`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Geometry {
+    /// `out center`
+    Center(f64, f64),
+    /// `out bb`
+    Bounds(BoundingBox),
+    /// `out geom` for ways. `None` for nodes that are missing or
+    /// clipped.
+    Line {
+        bounds: BoundingBox,
+        points: Vec<Option<(f64, f64)>>,
+    },
+    /// `out geom` for relations, per member. Relation members have
+    /// no points.
+    Members {
+        bounds: Option<BoundingBox>,
+        members: Vec<Vec<Option<(f64, f64)>>>,
+    },
+}
+
+/// `None` for nodes, which carry their coordinates anyway
+pub fn resolve(item: &Item, mode: &OutputGeometry, locations: &Locations) -> Option<Geometry> {
+    if item.is_node() {
+        return None;
+    }
+    match mode {
+        &OutputGeometry::None =>
+            None,
+        &OutputGeometry::Center =>
+            bounds(item, locations)
+            .map(|bounds| {
+                let (lat, lon) = bounds.center();
+                Geometry::Center(lat, lon)
+            }),
+        &OutputGeometry::Bounds =>
+            bounds(item, locations)
+            .map(Geometry::Bounds),
+        &OutputGeometry::Geom { ref clip } =>
+            match item.specific() {
+                &ItemSpecific::Way { ref refs } => {
+                    let points = clip_line(ref_points(refs, locations), clip);
+                    BoundingBox::from_points(points.iter().filter_map(|point| *point))
+                        .map(|bounds| Geometry::Line { bounds, points })
+                }
+                &ItemSpecific::Relation { ref members } => {
+                    let members = members.iter()
+                        .map(|&(_, ref member)| match member.item_type {
+                            ItemType::Node =>
+                                clip_line(vec![locations.node(member.id)], clip),
+                            ItemType::Way =>
+                                locations.way_refs(member.id)
+                                .map(|refs| clip_line(ref_points(refs, locations), clip))
+                                .unwrap_or_else(Vec::new),
+                            _ =>
+                                vec![],
+                        }).collect::<Vec<_>>();
+                    let bounds = BoundingBox::from_points(
+                        members.iter()
+                            .flat_map(|points| points.iter().filter_map(|point| *point))
+                    );
+                    Some(Geometry::Members { bounds, members })
+                }
+                _ =>
+                    None,
+            },
+    }
+}
+
+/// Of all known points of a way, or of the node and way members of
+/// a relation. Areas have their polygons.
+pub fn bounds(item: &Item, locations: &Locations) -> Option<BoundingBox> {
+    match item.specific() {
+        &ItemSpecific::Node { lat, lon } =>
+            Some(BoundingBox::new(lat, lon, lat, lon)),
+        &ItemSpecific::Way { ref refs } =>
+            BoundingBox::from_points(locations.ref_points(refs)),
+        &ItemSpecific::Relation { ref members } =>
+            BoundingBox::from_points(
+                members.iter()
+                    .flat_map(|&(_, ref member)| match member.item_type {
+                        ItemType::Node =>
+                            locations.node(member.id)
+                            .into_iter()
+                            .collect(),
+                        ItemType::Way =>
+                            locations.way_points(member.id)
+                            .unwrap_or_else(Vec::new),
+                        _ =>
+                            vec![],
+                    })
+            ),
+        &ItemSpecific::Area { ref multipolygon } =>
+            BoundingBox::from_points(
+                multipolygon.polygons.iter()
+                    .flat_map(|polygon| polygon.outer.points.iter().cloned())
+            ),
+        &ItemSpecific::Derived { .. } =>
+            None,
+    }
+}
+
+fn ref_points(refs: &[i64], locations: &Locations) -> Vec<Option<(f64, f64)>> {
+    refs.iter()
+        .map(|node_ref| locations.node(*node_ref as u64))
+        .collect()
+}
+
+/// Like Overpass, keeps the points inside the clip box and those
+/// adjacent to them, so that segments leaving the box are complete.
+fn clip_line(points: Vec<Option<(f64, f64)>>, clip: &Option<BoundingBox>) -> Vec<Option<(f64, f64)>> {
+    let clip = match clip {
+        &Some(ref clip) => clip,
+        &None => return points,
+    };
+    if points.len() == 1 {
+        return vec![points[0].filter(|&(lat, lon)| clip.contains(lat, lon))];
+    }
+    let crosses = |i: usize, j: usize| match (points[i], points[j]) {
+        (Some(a), Some(b)) => clip.intersects_segment(a, b),
+        _ => false,
+    };
+    (0..points.len())
+        .map(|i| {
+            let keep = (i > 0 && crosses(i - 1, i)) ||
+                (i + 1 < points.len() && crosses(i, i + 1));
+            if keep { points[i] } else { None }
+        }).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use bbox::BoundingBox;
+    use locations::Locations;
+    use ql::OutputGeometry;
+    use super::{resolve, Geometry};
+
+    /// A way along the equator from 0° to 3° with a node every
+    /// degree, and a relation with the way and a node
+    fn data() -> (Locations, Item, Item) {
+        let mut locations = Locations::new();
+        for i in 0..4 {
+            locations.insert_node(i + 1, 0.0, i as f64);
+        }
+        locations.insert_node(5, 1.0, 1.0);
+        locations.insert_way(10, vec![1, 2, 3, 4]);
+        let way = Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2, 3, 4] });
+        let relation = Item::new(20, HashMap::new(), ItemSpecific::Relation {
+            members: vec![
+                ("".to_owned(), ItemId::new(ItemType::Way, 10)),
+                ("".to_owned(), ItemId::new(ItemType::Node, 5)),
+                ("".to_owned(), ItemId::new(ItemType::Relation, 30)),
+            ],
+        });
+        (locations, way, relation)
+    }
+
+    #[test]
+    fn test_center_bounds() {
+        let (locations, way, relation) = data();
+        assert_eq!(resolve(&way, &OutputGeometry::Center, &locations),
+                   Some(Geometry::Center(0.0, 1.5)));
+        assert_eq!(resolve(&relation, &OutputGeometry::Bounds, &locations),
+                   Some(Geometry::Bounds(BoundingBox::new(0.0, 0.0, 1.0, 3.0))));
+        assert_eq!(resolve(&way, &OutputGeometry::None, &locations), None);
+    }
+
+    #[test]
+    fn test_geom() {
+        let (locations, way, relation) = data();
+        assert_eq!(resolve(&way, &OutputGeometry::Geom { clip: None }, &locations), Some(Geometry::Line {
+            bounds: BoundingBox::new(0.0, 0.0, 0.0, 3.0),
+            points: vec![Some((0.0, 0.0)), Some((0.0, 1.0)), Some((0.0, 2.0)), Some((0.0, 3.0))],
+        }));
+        assert_eq!(resolve(&relation, &OutputGeometry::Geom { clip: None }, &locations), Some(Geometry::Members {
+            bounds: Some(BoundingBox::new(0.0, 0.0, 1.0, 3.0)),
+            members: vec![
+                vec![Some((0.0, 0.0)), Some((0.0, 1.0)), Some((0.0, 2.0)), Some((0.0, 3.0))],
+                vec![Some((1.0, 1.0))],
+                vec![],
+            ],
+        }));
+    }
+
+    #[test]
+    fn test_geom_clipped() {
+        let (locations, way, relation) = data();
+        // Around the last segment
+        let clip = Some(BoundingBox::new(-0.5, 2.5, 0.5, 3.5));
+        assert_eq!(resolve(&way, &OutputGeometry::Geom { clip }, &locations), Some(Geometry::Line {
+            bounds: BoundingBox::new(0.0, 2.0, 0.0, 3.0),
+            points: vec![None, None, Some((0.0, 2.0)), Some((0.0, 3.0))],
+        }));
+        match resolve(&relation, &OutputGeometry::Geom { clip }, &locations) {
+            Some(Geometry::Members { members, .. }) =>
+                assert_eq!(members[1], vec![None]),
+            geometry =>
+                panic!("Unexpected {:?}", geometry),
+        }
+    }
+}
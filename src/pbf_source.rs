@@ -1,7 +1,8 @@
 use std::fs::File;
-use std::io::{Seek, SeekFrom, BufReader};
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom, BufReader};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::vec;
 use osm_pbf_iter::{Blob, BlobReader, Primitive};
 
 #[derive(Debug, Clone)]
@@ -23,6 +24,10 @@ impl PbfSource {
         }
     }
 
+    pub fn paths(&self) -> &[Arc<PathBuf>] {
+        &self.paths[..]
+    }
+
     pub fn all(&self) -> All {
         All {
             path_file: None,
@@ -30,6 +35,39 @@ impl PbfSource {
         }
     }
 
+    /// Blobs at the given offsets, in order
+    pub fn blobs_at(&self, blobs: Vec<(Arc<PathBuf>, u64)>) -> At {
+        At {
+            path_file: None,
+            remain: blobs.into_iter(),
+        }
+    }
+
+    /// `osmosis_replication_timestamp` from the header of the first
+    /// file, in seconds since the epoch
+    pub fn replication_timestamp(&self) -> Option<i64> {
+        let path = self.paths.first()?;
+        let mut file = BufReader::new(open(path));
+        // `BlobReader` skips the header blob
+        let mut len = [0; 4];
+        file.read_exact(&mut len).ok()?;
+        let blob_header = read_bytes(&mut file, u32::from_be_bytes(len) as usize)?;
+        let blob_header = fields(&blob_header)?;
+        if bytes_field(&blob_header, 1) != Some(b"OSMHeader") {
+            return None;
+        }
+        let datasize = varint_field(&blob_header, 3)?;
+        let blob = read_bytes(&mut file, datasize as usize)?;
+        let blob = fields(&blob)?;
+        let data = match (bytes_field(&blob, 1), bytes_field(&blob, 3)) {
+            (Some(raw), _) => Blob::Raw(raw.to_vec()),
+            (None, Some(zlib)) => Blob::Zlib(zlib.to_vec()),
+            (None, None) => return None,
+        }.into_data();
+        varint_field(&fields(&data)?, 32)
+            .map(|timestamp| timestamp as i64)
+    }
+
     // pub fn segments() {
     // }
 }
@@ -45,7 +83,8 @@ impl Iterator for All {
     fn next(&mut self) -> Option<Self::Item> {
         match self.path_file.take() {
             Some((path, mut file)) => {
-                let position = file.seek(SeekFrom::Current(0)).ok()?;
+                let position = file.seek(SeekFrom::Current(0))
+                    .unwrap_or_else(|e| panic!("Cannot seek in {}: {}", path.display(), e));
                 let result = BlobReader::read_blob(&mut file)
                     .map(|blob| (path.clone(), position, blob));
                 match result {
@@ -62,7 +101,7 @@ impl Iterator for All {
             }
             None if self.remain_paths.len() > 0 => {
                 let path = self.remain_paths.pop().unwrap();
-                let file = BufReader::new(File::open(path.as_ref()).unwrap());
+                let file = BufReader::new(open(&path));
                 self.path_file = Some((path, file));
                 self.next()
             }
@@ -70,3 +109,158 @@ impl Iterator for All {
         }
     }
 }
+
+pub struct At {
+    path_file: Option<(Arc<PathBuf>, BufReader<File>)>,
+    remain: vec::IntoIter<(Arc<PathBuf>, u64)>,
+}
+
+impl Iterator for At {
+    type Item = (Arc<PathBuf>, u64, Blob);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, offset) = self.remain.next()?;
+        let (path, mut file) = match self.path_file.take() {
+            // Keep the file open for consecutive blobs
+            Some((open_path, file)) if open_path == path =>
+                (open_path, file),
+            _ => {
+                let file = BufReader::new(open(&path));
+                (path, file)
+            }
+        };
+        // The index promised a blob here
+        file.seek(SeekFrom::Start(offset))
+            .unwrap_or_else(|e| panic!("Cannot seek to {} in {}: {}", offset, path.display(), e));
+        let blob = BlobReader::read_blob(&mut file)
+            .unwrap_or_else(|| panic!("Cannot read blob at {} in {}", offset, path.display()));
+        self.path_file = Some((path.clone(), file));
+        Some((path, offset, blob))
+    }
+}
+
+fn open(path: &Path) -> File {
+    File::open(path)
+        .unwrap_or_else(|e| panic!("Cannot open {}: {}", path.display(), e))
+}
+
+fn read_bytes<R: Read>(r: &mut R, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf).ok()?;
+    Some(buf)
+}
+
+/// Protobuf field value, as far as the header needs them
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Fields of a protobuf message by number. `None` if malformed.
+fn fields(data: &[u8]) -> Option<Vec<(u64, Field)>> {
+    let mut fields = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let value = match key & 7 {
+            0 =>
+                Field::Varint(read_varint(data, &mut pos)?),
+            1 | 5 => {
+                pos += if key & 7 == 1 { 8 } else { 4 };
+                Field::Fixed
+            }
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data.get(pos..pos + len)?;
+                pos += len;
+                Field::Bytes(bytes)
+            }
+            _ =>
+                return None,
+        };
+        fields.push((key >> 3, value));
+    }
+    Some(fields)
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut n = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        n |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+        if shift >= 64 {
+            return None;
+        }
+    }
+}
+
+fn varint_field(fields: &[(u64, Field)], number: u64) -> Option<u64> {
+    fields.iter()
+        .filter_map(|&(field_number, ref value)| match value {
+            &Field::Varint(n) if field_number == number => Some(n),
+            _ => None,
+        }).next()
+}
+
+fn bytes_field<'a>(fields: &[(u64, Field<'a>)], number: u64) -> Option<&'a [u8]> {
+    fields.iter()
+        .filter_map(|&(field_number, ref value)| match value {
+            &Field::Bytes(bytes) if field_number == number => Some(bytes),
+            _ => None,
+        }).next()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::io::Write;
+    use super::PbfSource;
+
+    fn varint(buf: &mut Vec<u8>, mut n: u64) {
+        while n >= 0x80 {
+            buf.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        buf.push(n as u8);
+    }
+
+    fn bytes(buf: &mut Vec<u8>, field: u64, data: &[u8]) {
+        varint(buf, field << 3 | 2);
+        varint(buf, data.len() as u64);
+        buf.extend_from_slice(data);
+    }
+
+    #[test]
+    fn test_replication_timestamp() {
+        let mut header_block = vec![];
+        bytes(&mut header_block, 16, b"OsmSchema-V0.6");
+        varint(&mut header_block, 32 << 3);
+        varint(&mut header_block, 1700000000);
+        let mut blob = vec![];
+        bytes(&mut blob, 1, &header_block);
+        let mut blob_header = vec![];
+        bytes(&mut blob_header, 1, b"OSMHeader");
+        varint(&mut blob_header, 3 << 3);
+        varint(&mut blob_header, blob.len() as u64);
+
+        let path = env::temp_dir().join(format!("underpass-turbo-test-{}.osm.pbf", process::id()));
+        {
+            let mut file = fs::File::create(&path).unwrap();
+            file.write_all(&(blob_header.len() as u32).to_be_bytes()).unwrap();
+            file.write_all(&blob_header).unwrap();
+            file.write_all(&blob).unwrap();
+        }
+        let timestamp = PbfSource::new(Some(path.clone()).into_iter())
+            .replication_timestamp();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(timestamp, Some(1700000000));
+    }
+}
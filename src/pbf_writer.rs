@@ -0,0 +1,442 @@
+//! OSM PBF output. Blobs are stored raw, which the format allows, so
+//! that no compression library is needed.
+
+use std::io::{self, Write};
+use std::collections::{BTreeMap, HashMap};
+
+use item::{Item, ItemSpecific, ItemId, ItemType};
+use set::Set;
+use locations::Locations;
+use ql::OutputGeometry;
+use writer::{Writer, sorted_tags};
+
+/// Elements per `PrimitiveBlock`, like osmium
+const BLOCK_SIZE: usize = 8000;
+/// Nanodegrees per coordinate unit, the format's default
+const GRANULARITY: i64 = 100;
+
+/// `[out:pbf]`. Collects the sets of all `out` statements, then
+/// writes them sorted by type and id. Areas and derived elements
+/// have no representation in PBF and are left out.
+pub struct PbfWriter<W: Write> {
+    out: W,
+    items: BTreeMap<ItemId, Item>,
+}
+
+impl<W: Write> PbfWriter<W> {
+    pub fn new(out: W) -> Self {
+        PbfWriter {
+            out,
+            items: BTreeMap::new(),
+        }
+    }
+
+    fn write_blob(&mut self, blob_type: &str, data: Vec<u8>) -> io::Result<()> {
+        let mut blob = Message::new();
+        blob.bytes(1, &data);
+        blob.varint(2, data.len() as u64);
+        let blob = blob.into_bytes();
+
+        let mut header = Message::new();
+        header.bytes(1, blob_type.as_bytes());
+        header.varint(3, blob.len() as u64);
+        let header = header.into_bytes();
+
+        self.out.write_all(&(header.len() as u32).to_be_bytes())?;
+        self.out.write_all(&header)?;
+        self.out.write_all(&blob)
+    }
+}
+
+impl<W: Write> Writer for PbfWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        let mut header_block = Message::new();
+        header_block.bytes(4, b"OsmSchema-V0.6");
+        header_block.bytes(4, b"DenseNodes");
+        header_block.bytes(5, b"Sort.Type_then_ID");
+        header_block.bytes(16, b"Underpass Turbo");
+        self.write_blob("OSMHeader", header_block.into_bytes())
+    }
+
+    fn write(&mut self, set: &Set, _geometry: &OutputGeometry, _locations: Option<&Locations>) -> io::Result<()> {
+        for item in set.iter() {
+            match item.item_type() {
+                ItemType::Node | ItemType::Way | ItemType::Relation => {
+                    self.items.insert(item.item_id(), item.clone());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        let items = ::std::mem::take(&mut self.items);
+        let mut block = vec![];
+        for (_, item) in items {
+            // Blocks hold only one type
+            let flush = block.len() >= BLOCK_SIZE ||
+                block.first().is_some_and(|first: &Item| first.item_type() != item.item_type());
+            if flush {
+                self.write_blob("OSMData", primitive_block(&block))?;
+                block.clear();
+            }
+            block.push(item);
+        }
+        if !block.is_empty() {
+            self.write_blob("OSMData", primitive_block(&block))?;
+        }
+        self.out.flush()
+    }
+}
+
+/// One `PrimitiveGroup` of items of the same type
+pub fn primitive_block(items: &[Item]) -> Vec<u8> {
+    let mut strings = StringTable::new();
+    let mut group = Message::new();
+    match items[0].item_type() {
+        ItemType::Node => {
+            let mut ids = Delta::new();
+            let mut lats = Delta::new();
+            let mut lons = Delta::new();
+            let mut keys_vals = vec![];
+            for item in items {
+                let (lat, lon) = item.get_lat_lon()
+                    .expect("Node without coordinates");
+                ids.push(item.id as i64);
+                lats.push(coordinate(lat));
+                lons.push(coordinate(lon));
+                for (k, v) in sorted_tags(item) {
+                    keys_vals.push(strings.index(k));
+                    keys_vals.push(strings.index(v));
+                }
+                keys_vals.push(0);
+            }
+            let mut dense = Message::new();
+            dense.bytes(1, &ids.into_bytes());
+            dense.bytes(8, &lats.into_bytes());
+            dense.bytes(9, &lons.into_bytes());
+            dense.bytes(10, &packed(&keys_vals));
+            group.bytes(2, &dense.into_bytes());
+        }
+        ItemType::Way => {
+            for item in items {
+                let mut way = element(item, &mut strings);
+                if let &ItemSpecific::Way { ref refs } = item.specific() {
+                    let mut deltas = Delta::new();
+                    for node_ref in refs {
+                        deltas.push(*node_ref);
+                    }
+                    way.bytes(8, &deltas.into_bytes());
+                }
+                group.bytes(3, &way.into_bytes());
+            }
+        }
+        ItemType::Relation => {
+            for item in items {
+                let mut relation = element(item, &mut strings);
+                if let &ItemSpecific::Relation { ref members } = item.specific() {
+                    let mut roles = vec![];
+                    let mut ids = Delta::new();
+                    let mut types = vec![];
+                    for &(ref role, ref member) in members {
+                        roles.push(strings.index(role));
+                        ids.push(member.id as i64);
+                        types.push(match member.item_type {
+                            ItemType::Node => 0,
+                            ItemType::Way => 1,
+                            _ => 2,
+                        });
+                    }
+                    relation.bytes(8, &packed(&roles));
+                    relation.bytes(9, &ids.into_bytes());
+                    relation.bytes(10, &packed(&types));
+                }
+                group.bytes(4, &relation.into_bytes());
+            }
+        }
+        item_type =>
+            panic!("Cannot write {:?} to PBF", item_type),
+    }
+
+    let mut block = Message::new();
+    block.bytes(1, &strings.into_bytes());
+    block.bytes(2, &group.into_bytes());
+    block.varint(17, GRANULARITY as u64);
+    block.into_bytes()
+}
+
+/// Id and tags of a `Way` or `Relation`
+fn element(item: &Item, strings: &mut StringTable) -> Message {
+    let mut keys = vec![];
+    let mut vals = vec![];
+    for (k, v) in sorted_tags(item) {
+        keys.push(strings.index(k));
+        vals.push(strings.index(v));
+    }
+    let mut message = Message::new();
+    message.varint(1, item.id);
+    message.bytes(2, &packed(&keys));
+    message.bytes(3, &packed(&vals));
+    message
+}
+
+fn coordinate(degrees: f64) -> i64 {
+    (degrees * 1e9 / GRANULARITY as f64).round() as i64
+}
+
+/// Strings are referenced by index. Index 0 is the empty string,
+/// which doubles as the delimiter of dense node tags.
+struct StringTable {
+    strings: Vec<String>,
+    indexes: HashMap<String, u64>,
+}
+
+impl StringTable {
+    fn new() -> Self {
+        let mut indexes = HashMap::new();
+        indexes.insert(String::new(), 0);
+        StringTable {
+            strings: vec![String::new()],
+            indexes,
+        }
+    }
+
+    fn index(&mut self, s: &str) -> u64 {
+        if let Some(index) = self.indexes.get(s) {
+            return *index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(s.to_owned());
+        self.indexes.insert(s.to_owned(), index);
+        index
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut message = Message::new();
+        for s in &self.strings {
+            message.bytes(1, s.as_bytes());
+        }
+        message.into_bytes()
+    }
+}
+
+/// Packed, delta-coded `sint64`
+struct Delta {
+    last: i64,
+    buf: Vec<u8>,
+}
+
+impl Delta {
+    fn new() -> Self {
+        Delta { last: 0, buf: vec![] }
+    }
+
+    fn push(&mut self, n: i64) {
+        write_varint(&mut self.buf, zigzag(n - self.last));
+        self.last = n;
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Protobuf encoding of the few wire types we need
+struct Message {
+    buf: Vec<u8>,
+}
+
+impl Message {
+    fn new() -> Self {
+        Message { buf: vec![] }
+    }
+
+    fn varint(&mut self, field: u32, n: u64) {
+        write_varint(&mut self.buf, u64::from(field) << 3);
+        write_varint(&mut self.buf, n);
+    }
+
+    fn bytes(&mut self, field: u32, data: &[u8]) {
+        write_varint(&mut self.buf, u64::from(field) << 3 | 2);
+        write_varint(&mut self.buf, data.len() as u64);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+fn packed(values: &[u64]) -> Vec<u8> {
+    let mut buf = vec![];
+    for value in values {
+        write_varint(&mut buf, *value);
+    }
+    buf
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        buf.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.push(n as u8);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use geometry::MultiPolygon;
+    use ql::OutputGeometry;
+    use writer::Writer;
+    use super::{PbfWriter, write_varint, zigzag};
+
+    #[derive(Debug, PartialEq)]
+    enum Field {
+        Varint(u64),
+        Bytes(Vec<u8>),
+    }
+
+    fn read_varint(data: &[u8], pos: &mut usize) -> u64 {
+        let mut n = 0;
+        let mut shift = 0;
+        loop {
+            let byte = data[*pos];
+            *pos += 1;
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                return n;
+            }
+            shift += 7;
+        }
+    }
+
+    /// Just enough protobuf decoding to check what we wrote
+    fn fields(data: &[u8]) -> Vec<(u64, Field)> {
+        let mut pos = 0;
+        let mut fields = vec![];
+        while pos < data.len() {
+            let key = read_varint(data, &mut pos);
+            let field = match key & 7 {
+                0 => Field::Varint(read_varint(data, &mut pos)),
+                2 => {
+                    let len = read_varint(data, &mut pos) as usize;
+                    pos += len;
+                    Field::Bytes(data[pos - len..pos].to_vec())
+                }
+                wire_type => panic!("Unexpected wire type {}", wire_type),
+            };
+            fields.push((key >> 3, field));
+        }
+        fields
+    }
+
+    fn bytes(fields: &[(u64, Field)], number: u64) -> Vec<&[u8]> {
+        fields.iter()
+            .filter_map(|&(n, ref field)| match field {
+                &Field::Bytes(ref data) if n == number => Some(&data[..]),
+                _ => None,
+            }).collect()
+    }
+
+    fn packed(data: &[u8]) -> Vec<u64> {
+        let mut pos = 0;
+        let mut values = vec![];
+        while pos < data.len() {
+            values.push(read_varint(data, &mut pos));
+        }
+        values
+    }
+
+    #[test]
+    fn test_varint() {
+        let mut buf = vec![];
+        write_varint(&mut buf, 300);
+        assert_eq!(buf, vec![0xac, 0x02]);
+        assert_eq!(zigzag(0), 0);
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+        assert_eq!(zigzag(-2), 3);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut set = Set::empty();
+        let mut tags = HashMap::new();
+        tags.insert("name".to_owned(), "C3D2".to_owned());
+        set.insert(Item::new(5, tags.clone(), ItemSpecific::Node { lat: 51.0, lon: -13.7 }));
+        set.insert(Item::new(1, HashMap::new(), ItemSpecific::Node { lat: 51.1, lon: 13.7 }));
+        set.insert(Item::new(10, tags, ItemSpecific::Way { refs: vec![1, 5] }));
+        set.insert(Item::new(20, HashMap::new(), ItemSpecific::Relation {
+            members: vec![("".to_owned(), ItemId::new(ItemType::Way, 10))],
+        }));
+        set.insert(Item::new(3600000020, HashMap::new(), ItemSpecific::Area {
+            multipolygon: Arc::new(MultiPolygon::default()),
+        }));
+        let mut out = vec![];
+        {
+            let mut writer = PbfWriter::new(&mut out);
+            writer.begin().unwrap();
+            writer.write(&set, &OutputGeometry::None, None).unwrap();
+            writer.end().unwrap();
+        }
+
+        let mut blocks = vec![];
+        let mut pos = 0;
+        while pos < out.len() {
+            let mut len = [0; 4];
+            len.copy_from_slice(&out[pos..pos + 4]);
+            let header_len = u32::from_be_bytes(len) as usize;
+            pos += 4;
+            let header = fields(&out[pos..pos + header_len]);
+            pos += header_len;
+            let blob_type = String::from_utf8(bytes(&header, 1)[0].to_vec()).unwrap();
+            let blob_len = match header[1] {
+                (3, Field::Varint(blob_len)) => blob_len as usize,
+                ref field => panic!("Unexpected {:?}", field),
+            };
+            let blob = fields(&out[pos..pos + blob_len]);
+            pos += blob_len;
+            let raw = bytes(&blob, 1)[0].to_vec();
+            assert_eq!(blob[1], (2, Field::Varint(raw.len() as u64)));
+            blocks.push((blob_type, raw));
+        }
+        assert_eq!(blocks.iter().map(|&(ref blob_type, _)| blob_type.as_str()).collect::<Vec<_>>(),
+                   vec!["OSMHeader", "OSMData", "OSMData", "OSMData"]);
+
+        // Dense nodes, sorted by id
+        let block = fields(&blocks[1].1);
+        let strings = fields(bytes(&block, 1)[0]);
+        assert_eq!(bytes(&strings, 1), vec![&b""[..], &b"name"[..], &b"C3D2"[..]]);
+        let group = fields(bytes(&block, 2)[0]);
+        let dense = fields(bytes(&group, 2)[0]);
+        assert_eq!(packed(bytes(&dense, 1)[0]), vec![zigzag(1), zigzag(4)]);
+        assert_eq!(packed(bytes(&dense, 8)[0]), vec![zigzag(511000000), zigzag(-1000000)]);
+        assert_eq!(packed(bytes(&dense, 9)[0]), vec![zigzag(137000000), zigzag(-274000000)]);
+        assert_eq!(packed(bytes(&dense, 10)[0]), vec![0, 1, 2, 0]);
+
+        // Way with delta-coded refs
+        let block = fields(&blocks[2].1);
+        let group = fields(bytes(&block, 2)[0]);
+        let way = fields(bytes(&group, 3)[0]);
+        assert_eq!(way[0], (1, Field::Varint(10)));
+        assert_eq!(packed(bytes(&way, 8)[0]), vec![zigzag(1), zigzag(4)]);
+
+        // Relation with an empty role
+        let block = fields(&blocks[3].1);
+        let group = fields(bytes(&block, 2)[0]);
+        let relation = fields(bytes(&group, 4)[0]);
+        assert_eq!(packed(bytes(&relation, 8)[0]), vec![0]);
+        assert_eq!(packed(bytes(&relation, 9)[0]), vec![zigzag(10)]);
+        assert_eq!(packed(bytes(&relation, 10)[0]), vec![1]);
+    }
+}
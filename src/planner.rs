@@ -1,53 +1,249 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread::JoinHandle;
 use std::collections::{HashMap, HashSet};
-use std::collections::hash_map::Entry;
+use osm_pbf_iter::Primitive;
 
 use trace_node::{UniqueSet, TraceNode, Trace};
-use process_node::{ProcessNode, Process};
-use query::QueryTarget;
+use process_node::{ProcessNode, Process, Message, ResultSender};
+use process::Runner;
+use recurse::Recursion;
+use set::Set;
+use filter::{Context, may_match};
+use area::{AreaStore, is_area_candidate};
+use locations::IsCandidate;
+use writer::Writer;
+use ql::{Filter, RecurseType};
 
 pub struct Plan {
     outputs: HashMap<UniqueSet, TraceNode>,
     passes: Vec<Vec<UniqueSet>>,
+    /// Run an additional pass for way and relation geometry
+    needs_locations: bool,
+    /// Load or build the `AreaStore`
+    needs_areas: bool,
 }
 
 impl Plan {
-    pub fn run(&self) {
-        let mut nodes_by_output = HashMap::new();
-        let mut nodes_by_input = HashMap::new();
-
-        for (output, trace_node) in self.outputs.iter() {
-            // TODO: processor() instead of clone()
-            let process = trace_node.process.clone();
-            let process_node = Rc::new(ProcessNode::new(process));
-            nodes_by_output.insert(output, process_node.clone());
-            for input_set in &trace_node.input_sets {
-                match nodes_by_input.entry(input_set.clone()) {
-                    Entry::Vacant(mut e) => {
-                        e.insert(vec![process_node.clone()]);
+    /// Writes the sets of `out` statements in statement order
+    pub fn run(&self, runner: &Runner, writer: &mut dyn Writer) {
+        let mut context = Context::new();
+        // Only complete locations can be reused to build areas
+        let mut all_locations = None;
+        if self.needs_locations || writer.needs_locations() {
+            eprintln!("Collecting locations");
+            let queries = self.query_filters();
+            let blobs = queries.as_ref()
+                .and_then(|queries| runner.location_blobs(queries));
+            let is_candidate = queries.map(|queries| {
+                let queries = queries.into_iter()
+                    .map(|filters| filters.to_vec())
+                    .collect::<Vec<_>>();
+                Arc::new(move |primitive: &Primitive| queries.iter()
+                         .any(|filters| may_match(filters, primitive))
+                ) as IsCandidate
+            });
+            let is_complete = blobs.is_none() && is_candidate.is_none();
+            let collected = Arc::new(runner.collect_locations(blobs, is_candidate));
+            context.set_locations(collected.clone());
+            if is_complete {
+                all_locations = Some(collected);
+            }
+        }
+        let areas = if self.needs_areas {
+            let areas = AreaStore::open(runner, || match all_locations {
+                Some(ref locations) => locations.clone(),
+                None => {
+                    eprintln!("Collecting locations");
+                    let is_candidate = Arc::new(|primitive: &Primitive| is_area_candidate(primitive));
+                    Arc::new(runner.collect_locations(None, Some(is_candidate)))
+                }
+            });
+            eprintln!("{} areas", areas.len());
+            let areas = Arc::new(areas);
+            context.set_areas(areas.clone());
+            Some(areas)
+        } else {
+            None
+        };
+
+        let (result_tx, result_rx) = channel();
+        let mut results = Results::new(result_rx);
+        let (node_txs, node_threads) = self.spawn_graph(result_tx);
+
+        for (pass, pass_outputs) in self.passes.iter().enumerate() {
+            eprintln!("Running pass {}", pass);
+            let mut targets = vec![];
+            let mut recursions = HashMap::new();
+            for output in pass_outputs {
+                let trace_node = &self.outputs[output];
+                match trace_node.process {
+                    Process::Recurse(recurse_type) => {
+                        let input = trace_node.input_sets.iter()
+                            .next()
+                            .expect("Recurse without input set");
+                        let recursion = Recursion::new(recurse_type, &results.get(input));
+                        recursions.insert(*output, recursion);
+                    }
+                    Process::IsIn { lat_lon } => {
+                        let areas = areas.as_ref()
+                            .expect("is_in without areas");
+                        let found = match lat_lon {
+                            Some(lat_lon) =>
+                                areas.is_in(Some(lat_lon).into_iter()),
+                            None => {
+                                let input = trace_node.input_sets.iter()
+                                    .next()
+                                    .expect("is_in without input set");
+                                let input = results.get(input);
+                                areas.is_in(input.iter().filter_map(|item| item.get_lat_lon()))
+                            }
+                        };
+                        node_txs[output].send(Message::Results(found))
+                            .expect("Process failed");
+                    }
+                    ref process => {
+                        let target = process.query_target(context.clone(), |set| results.get(set))
+                            .expect("Pass without query target");
+                        match areas {
+                            Some(ref areas) if target.only_areas() =>
+                                node_txs[output].send(Message::Results(areas.query(&target)))
+                                .expect("Process failed"),
+                            _ =>
+                                targets.push((*output, target)),
+                        }
+                    }
+                }
+            }
+
+            // Recursions take additional scans until they are complete
+            loop {
+                for (output, recursion) in recursions.iter() {
+                    if let Some(target) = recursion.query_target() {
+                        targets.push((*output, target));
                     }
-                    Entry::Occupied(mut e) => {
-                        e.get_mut().push(process_node.clone());
+                }
+                if targets.is_empty() {
+                    break;
+                }
+
+                let scan_results = runner.run_all(&targets);
+                targets.clear();
+                for (output, set) in scan_results {
+                    match recursions.get_mut(&output) {
+                        Some(recursion) =>
+                            recursion.advance(set),
+                        None =>
+                            node_txs[&output].send(Message::Results(set))
+                            .expect("Process failed"),
                     }
                 }
             }
+            for (output, recursion) in recursions {
+                node_txs[&output].send(Message::Results(recursion.into_results()))
+                    .expect("Process failed");
+            }
         }
 
-        // Connect nodes
-        for (output, ref mut process_node) in nodes_by_output.iter_mut() {
-            for target in nodes_by_input.get(output).unwrap() {
-                Rc::get_mut(process_node)
-                    .unwrap()
-                    .add_target(target.clone());
+        let mut outputs = self.outputs.iter()
+            .filter(|&(_, trace_node)| trace_node.process.is_output())
+            .collect::<Vec<_>>();
+        // Unique sets are numbered in statement order
+        outputs.sort_by_key(|&(output, _)| *output);
+        writer.begin()
+            .expect("Cannot write output");
+        for (output, trace_node) in outputs {
+            if let Process::Output { ref geometry } = trace_node.process {
+                writer.write(&results.get(output), geometry, context.locations())
+                    .expect("Cannot write output");
             }
         }
+        writer.end()
+            .expect("Cannot write output");
 
-        for (pass, _target) in self.passes.iter().enumerate() {
-            println!("Running pass {}", pass);
-            
+        // Let processes blocked by a failed input terminate
+        drop(node_txs);
+        for node_thread in node_threads {
+            node_thread.join()
+                .expect("Process failed");
         }
     }
+
+    /// Filters of all queries. `None` if a recursion may find
+    /// elements anywhere.
+    fn query_filters(&self) -> Option<Vec<&[Filter]>> {
+        let mut queries = vec![];
+        for trace_node in self.outputs.values() {
+            match trace_node.process {
+                Process::Query { ref filters, .. } =>
+                    queries.push(&filters[..]),
+                // Members of what the queries found
+                Process::Recurse(RecurseType::Down) =>
+                    (),
+                Process::Recurse(_) =>
+                    return None,
+                _ =>
+                    (),
+            }
+        }
+        Some(queries)
+    }
+
+    /// Returns message inputs by output set, and threads to join
+    fn spawn_graph(&self, result_tx: ResultSender) -> (HashMap<UniqueSet, Sender<Message>>, Vec<JoinHandle<()>>) {
+        let mut channels = self.outputs.keys()
+            .map(|output| (*output, channel()))
+            .collect::<HashMap<_, _>>();
+        let node_txs = channels.iter()
+            .map(|(output, &(ref tx, _))| (*output, tx.clone()))
+            .collect::<HashMap<_, _>>();
+
+        let node_threads = self.outputs.iter()
+            .map(|(output, trace_node)| {
+                // TODO: processor() instead of clone()
+                let mut process_node = ProcessNode::new(
+                    *output,
+                    trace_node.process.clone(),
+                    trace_node.input_sets.clone()
+                );
+                // Connect nodes
+                for (target, target_node) in self.outputs.iter() {
+                    if target_node.input_sets.contains(output) {
+                        process_node.add_target(node_txs[target].clone());
+                    }
+                }
+
+                let (_, rx) = channels.remove(output).unwrap();
+                process_node.spawn(rx, result_tx.clone())
+            }).collect();
+
+        (node_txs, node_threads)
+    }
+}
+
+/// Result sets of completed processes
+struct Results {
+    rx: Receiver<(UniqueSet, Arc<Set>)>,
+    sets: HashMap<UniqueSet, Arc<Set>>,
+}
+
+impl Results {
+    fn new(rx: Receiver<(UniqueSet, Arc<Set>)>) -> Self {
+        Results {
+            rx,
+            sets: HashMap::new(),
+        }
+    }
+
+    /// Waits for the process to complete
+    fn get(&mut self, set: &UniqueSet) -> Arc<Set> {
+        while !self.sets.contains_key(set) {
+            let (output, results) = self.rx.recv()
+                .expect("Process failed");
+            self.sets.insert(output, results);
+        }
+        self.sets[set].clone()
+    }
 }
 
 pub fn plan(trace: &Trace) -> Plan {
@@ -55,14 +251,14 @@ pub fn plan(trace: &Trace) -> Plan {
     trace.trace_back_from_outputs(&mut |output, trace_node| {
         required_outputs.insert(output);
     });
-    println!("required outputs: {:?}", required_outputs);
+    eprintln!("required outputs: {:?}", required_outputs);
 
     // let mut passes = vec![];
     let mut outputs = HashMap::<UniqueSet, TraceNode>::new();
     let mut processed_inputs = HashSet::new();
     let mut passes = vec![];
     while required_outputs.len() > 0 {
-        println!("Pass {}", passes.len());
+        eprintln!("Pass {}", passes.len());
         let mut pass = vec![];
 
         // For a first step in a pass, all those nodes with data input
@@ -73,8 +269,8 @@ pub fn plan(trace: &Trace) -> Plan {
         required_outputs.retain(|output| {
             let node = trace.get_by_output(*output).unwrap();
             if node.are_all_inputs_satisfied(&processed_inputs) {
-                if let Some(target) = node.process.query_target() {
-                    println!("Query {:?}: {:?}", output, node.process);
+                if node.process.is_query() {
+                    eprintln!("Query {:?}: {:?}", output, node.process);
                     outputs.insert(*output, node.clone());
                     // inputs.alter( += node.input_sets
                     pass.push(*output);
@@ -102,7 +298,7 @@ pub fn plan(trace: &Trace) -> Plan {
             required_outputs.retain(|output| {
                 let node = trace.get_by_output(*output).unwrap();
                 if node.are_all_inputs_satisfied(&processed_inputs) {
-                    println!("Map {:?}: {:?}", output, node.process);
+                    eprintln!("Map {:?}: {:?}", output, node.process);
                     outputs.insert(*output, node.clone());
                     false
                 } else {
@@ -117,9 +313,16 @@ pub fn plan(trace: &Trace) -> Plan {
         // println!("required_outputs.len: {:?}", required_outputs.len());
     }
 
+    let needs_locations = outputs.values()
+        .any(|trace_node| trace_node.process.needs_locations());
+    let needs_areas = outputs.values()
+        .any(|trace_node| trace_node.process.needs_areas());
+
     Plan {
         outputs,
         passes,
+        needs_locations,
+        needs_areas,
     }
 }
 
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use geometry::{Ring, Polygon, MultiPolygon};
+
+/// Polygon of a `(poly:"lat lon lat lon ...")` filter
+pub fn from_lat_lons(s: &str) -> Result<MultiPolygon, &'static str> {
+    let coords = s.split_whitespace()
+        .map(|coord| coord.parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| "poly coordinates must be numbers")?;
+    if coords.len() % 2 != 0 {
+        return Err("poly needs pairs of lat lon");
+    }
+    let points = coords.chunks(2)
+        .map(|lat_lon| (lat_lon[0], lat_lon[1]))
+        .collect::<Vec<_>>();
+    if points.len() < 3 {
+        return Err("poly needs at least three points");
+    }
+    Ok(MultiPolygon::new(vec![Polygon {
+        outer: closed_ring(points),
+        inners: vec![],
+    }]))
+}
+
+pub fn read_poly_file<P: AsRef<Path>>(path: P) -> io::Result<MultiPolygon> {
+    let mut input = String::new();
+    File::open(path)?.read_to_string(&mut input)?;
+    parse_poly(&input)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Osmosis polygon format: a name line, then sections of `lon lat`
+/// lines, each terminated by `END`. Sections whose name starts with
+/// `!` are holes.
+pub fn parse_poly(input: &str) -> Result<MultiPolygon, String> {
+    let mut lines = input.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        // Skip the name
+        .skip(1);
+    let mut outers = vec![];
+    let mut inners = vec![];
+    loop {
+        let section = match lines.next() {
+            Some("END") | None => break,
+            Some(section) => section,
+        };
+        let mut points = vec![];
+        loop {
+            let line = lines.next()
+                .ok_or_else(|| format!("Section {} without END", section))?;
+            if line == "END" {
+                break;
+            }
+            let coords = line.split_whitespace()
+                .map(|coord| coord.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{}: {}", line, e))?;
+            match coords.as_slice() {
+                &[lon, lat] => points.push((lat, lon)),
+                _ => return Err(format!("Expected lon lat: {}", line)),
+            }
+        }
+        if points.len() < 3 {
+            return Err(format!("Section {} has less than three points", section));
+        }
+        if section.starts_with('!') {
+            inners.push(closed_ring(points));
+        } else {
+            outers.push(closed_ring(points));
+        }
+    }
+
+    let mut polygons = outers.into_iter()
+        .map(|outer| Polygon { outer, inners: vec![] })
+        .collect::<Vec<_>>();
+    for inner in inners {
+        let point = inner.points[0];
+        match polygons.iter_mut().find(|polygon| polygon.outer.contains(point)) {
+            Some(polygon) => polygon.inners.push(inner),
+            None => return Err("Hole outside of any polygon".to_owned()),
+        }
+    }
+    Ok(MultiPolygon::new(polygons))
+}
+
+fn closed_ring(mut points: Vec<(f64, f64)>) -> Ring {
+    if points.first() != points.last() {
+        let first = points[0];
+        points.push(first);
+    }
+    Ring::new(points)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{from_lat_lons, parse_poly};
+
+    #[test]
+    fn test_from_lat_lons() {
+        let multipolygon = from_lat_lons("51.0 13.0  51.0 14.0 52.0 14.0").unwrap();
+        assert_eq!(multipolygon.polygons[0].outer.points.len(), 4);
+        assert!(multipolygon.contains((51.2, 13.9)));
+        assert!(!multipolygon.contains((51.8, 13.1)));
+        assert!(from_lat_lons("51.0 13.0 51.0").is_err());
+        assert!(from_lat_lons("51.0 13.0 x y 1 2").is_err());
+    }
+
+    #[test]
+    fn test_parse_poly() {
+        let multipolygon = parse_poly("dresden
+1
+   13.0   51.0
+   14.0   51.0
+   14.0   52.0
+   13.0   52.0
+END
+!1
+   13.4   51.4
+   13.6   51.4
+   13.6   51.6
+   13.4   51.6
+END
+END
+").unwrap();
+        assert_eq!(multipolygon.polygons.len(), 1);
+        assert_eq!(multipolygon.polygons[0].inners.len(), 1);
+        assert!(multipolygon.contains((51.2, 13.2)));
+        assert!(!multipolygon.contains((51.5, 13.5)));
+        assert!(!multipolygon.contains((50.5, 13.5)));
+    }
+}
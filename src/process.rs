@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::mpsc::sync_channel;
 use threadpool::{self, ThreadPool};
 use osm_pbf_iter::{Primitive, PrimitiveBlock, Blob};
@@ -11,33 +11,144 @@ use pbf_source::PbfSource;
 use ql::{Statement, Filter, TagSpec, QueryType};
 use filter::eval_filter;
 use trace_node::UniqueSet;
-use process_node::ProcessNode;
+use query::QueryTarget;
+use locations::{Locations, IsCandidate, References, WantedNodes};
+use spatial_index::SpatialIndex;
 
 enum Task {
-    Blob(Blob),
+    Blob(Arc<PathBuf>, u64, Blob),
     Finish,
 }
-unsafe impl Send for Task {}
 
 pub struct Runner {
     source: PbfSource,
     pool: ThreadPool,
+    /// Narrows down the blobs to read for a query
+    index: Option<SpatialIndex>,
 }
 
 impl Runner {
     pub fn new(source: PbfSource) -> Self {
         let pool = threadpool::Builder::new().build();
-        Runner { source, pool }
+        Runner { source, pool, index: None }
     }
 
-    // pub fn run_all(&self, processor_factories: &[ProcessorFactory]) -> Set {
-    //     self.run(
-    //         self.source.all()
-    //             // TODO: don't drop path+offset
-    //             .map(|t| t.2),
-    //         processor_factories
-    //     )
-    // }
+    pub fn set_index(&mut self, index: SpatialIndex) {
+        self.index = Some(index);
+    }
+
+    /// Scan all data once, collecting a result set for each query
+    /// target.
+    pub fn run_all(&self, targets: &[(UniqueSet, QueryTarget)]) -> HashMap<UniqueSet, Set> {
+        let processor_factories = Arc::new(
+            targets.iter()
+                .map(|&(output, ref target)|
+                     ProcessorFactory::new(output, target.clone())
+                ).collect::<Vec<_>>()
+        );
+        let generate = move || processor_factories.iter()
+            .map(
+                |processor_factory| processor_factory.generate()
+            ).collect::<Vec<_>>();
+        let selection = self.index.as_ref()
+            .and_then(|index| index.select(targets));
+        let workers = match selection {
+            Some(blobs) => {
+                eprintln!("Reading {} blobs", blobs.len());
+                self.run(self.source.blobs_at(blobs), generate)
+            }
+            None =>
+                self.run(self.source.all(), generate),
+        };
+
+        let mut result_sets = HashMap::new();
+        for processors in workers {
+            for processor in processors {
+                let (output, set) = processor.digest();
+                result_sets.entry(output)
+                    .or_insert_with(Vec::new)
+                    .push(set);
+            }
+        }
+        result_sets.into_iter()
+            .map(|(output, sets)| (output, Set::merge(sets.into_iter())))
+            .collect()
+    }
+
+    /// Blobs with the geometry of all that the queries may match,
+    /// if the index can tell
+    pub fn location_blobs(&self, queries: &[&[Filter]]) -> Option<Vec<(Arc<PathBuf>, u64)>> {
+        self.index.as_ref()
+            .and_then(|index| index.select_locations(queries))
+    }
+
+    /// Node locations and way refs, from all blobs unless given.
+    /// With `is_candidate`, only the geometry of the ways and
+    /// relations it accepts, which takes up to three scans but keeps
+    /// memory use to what is referenced.
+    pub fn collect_locations(&self, blobs: Option<Vec<(Arc<PathBuf>, u64)>>, is_candidate: Option<IsCandidate>) -> Locations {
+        let is_candidate = match is_candidate {
+            Some(is_candidate) => is_candidate,
+            None => {
+                let workers = self.scan(blobs, Locations::new);
+                return Locations::merge(workers.into_iter());
+            }
+        };
+
+        let workers = self.scan(blobs.clone(), move || References::new(is_candidate.clone()));
+        let (mut locations, mut nodes, ways) = merge_references(workers);
+        // Way members of candidate relations
+        let missing_ways = ways.into_iter()
+            .filter(|id| locations.way_refs(*id).is_none())
+            .collect::<HashSet<_>>();
+        if !missing_ways.is_empty() {
+            let missing_ways = Arc::new(missing_ways);
+            let is_missing: IsCandidate = Arc::new(move |primitive| match primitive {
+                &Primitive::Way(ref way) => missing_ways.contains(&way.id),
+                _ => false,
+            });
+            let workers = self.scan(blobs.clone(), move || References::new(is_missing.clone()));
+            let (way_locations, way_nodes, _) = merge_references(workers);
+            locations = Locations::merge(vec![locations, way_locations].into_iter());
+            nodes.extend(way_nodes);
+        }
+
+        let nodes = Arc::new(nodes);
+        let workers = self.scan(blobs, move || WantedNodes::new(nodes.clone()));
+        Locations::merge(
+            Some(locations).into_iter()
+                .chain(workers.into_iter().map(|worker| worker.locations))
+        )
+    }
+
+    /// Scan the given blobs, or all data
+    fn scan<W, F>(&self, blobs: Option<Vec<(Arc<PathBuf>, u64)>>, generate: F) -> Vec<W>
+    where
+        W: Worker,
+        F: Fn() -> W + Send + Sync + 'static,
+    {
+        match blobs {
+            Some(blobs) => {
+                eprintln!("Reading {} blobs", blobs.len());
+                self.run(self.source.blobs_at(blobs), generate)
+            }
+            None =>
+                self.scan_all(generate),
+        }
+    }
+
+    /// Scan all data once with custom workers
+    pub fn scan_all<W, F>(&self, generate: F) -> Vec<W>
+    where
+        W: Worker,
+        F: Fn() -> W + Send + Sync + 'static,
+    {
+        self.run(self.source.all(), generate)
+    }
+
+    pub fn source(&self) -> &PbfSource {
+        &self.source
+    }
 
     // pub fn run_segments<I, F>(paths: I, mut f: F)
     // where
@@ -54,115 +165,153 @@ impl Runner {
     //     }
     // }
 
-    // fn run<I>(&self, iter: I, processor_factories: &[ProcessorFactory]) -> Set
-    // where
-    //     I: Iterator<Item=Blob>,
-    // {
-    //     // Prepare workers
-    //     let processor_factories = Arc::new(processor_factories);
-    //     let worker_count = self.pool.max_count();
-    //     let mut task_txs = Vec::with_capacity(worker_count);
-    //     let mut res_rxs = Vec::with_capacity(worker_count);
-
-    //     for _ in 0..worker_count {
-    //         let processor_factories = processor_factories.clone();
-
-    //         let (task_tx, task_rx) = sync_channel(1);
-    //         task_txs.push(task_tx);
-
-    //         let (res_tx, res_rx) = sync_channel(1);
-    //         res_rxs.push(res_rx);
-
-    //         self.pool.execute(move || {
-    //             let mut processors = processor_factories.iter()
-    //                 .map(
-    //                     |processor_factory| processor_factory.generate()
-    //                 );
-    //             while let Ok(task) = task_rx.recv() {
-    //                 match task {
-    //                     Task::Blob(blob) => {
-    //                         let data = blob.into_data();
-    //                         let primitive_block = PrimitiveBlock::parse(&data);
-    //                         for primitive in primitive_block.primitives() {
-    //                             let item = primitive.into();
-    //                             for processor in processors {
-    //                                 processor.process(item);
-    //                             }
-    //                         }
-    //                     }
-    //                     Task::Finish => {
-    //                         break
-    //                     }
-    //                 }
-    //             }
-    //             let set = processors.digest();
-    //             res_tx.send(set);
-    //         });
-    //     }
-    //     // Feed tasks
-    //     let mut i = 0;
-    //     for blob in iter {
-    //         task_txs[i].send(Task::Blob(blob));
-
-    //         i += 1;
-    //         if i >= worker_count {
-    //             i = 0;
-    //         }
-    //     }
-    //     // Finish up
-    //     let mut result_sets = Vec::with_capacity(worker_count);
-    //     for (task_tx, res_rx) in task_txs.iter().zip(res_rxs) {
-    //         task_tx.send(Task::Finish);
+    /// Returns the state of all workers
+    fn run<I, W, F>(&self, iter: I, generate: F) -> Vec<W>
+    where
+        I: Iterator<Item=(Arc<PathBuf>, u64, Blob)>,
+        W: Worker,
+        F: Fn() -> W + Send + Sync + 'static,
+    {
+        // Prepare workers
+        let generate = Arc::new(generate);
+        let worker_count = self.pool.max_count();
+        let mut task_txs = Vec::with_capacity(worker_count);
+        let mut res_rxs = Vec::with_capacity(worker_count);
 
-    //         let set = res_rx.recv().unwrap();
-    //         result_sets.push(set);
-    //     }
-    //     let result_set = Set::merge(result_sets.into_iter());
-    //     self.pool.join();
-    //     result_set
-    // }
+        for _ in 0..worker_count {
+            let generate = generate.clone();
+
+            let (task_tx, task_rx) = sync_channel(1);
+            task_txs.push(task_tx);
+
+            let (res_tx, res_rx) = sync_channel(1);
+            res_rxs.push(res_rx);
+
+            self.pool.execute(move || {
+                let mut worker = generate();
+                while let Ok(task) = task_rx.recv() {
+                    match task {
+                        Task::Blob(path, offset, blob) => {
+                            worker.begin_blob(&path, offset);
+                            let data = blob.into_data();
+                            let primitive_block = PrimitiveBlock::parse(&data);
+                            for primitive in primitive_block.primitives() {
+                                worker.process(&primitive);
+                            }
+                        }
+                        Task::Finish => {
+                            break
+                        }
+                    }
+                }
+                // Receiver may be gone if another worker failed
+                let _ = res_tx.send(worker);
+            });
+        }
+        // Feed tasks
+        let mut i = 0;
+        for (path, offset, blob) in iter {
+            task_txs[i].send(Task::Blob(path, offset, blob))
+                .expect("Worker failed");
+
+            i += 1;
+            if i >= worker_count {
+                i = 0;
+            }
+        }
+        // Finish up
+        let mut workers = Vec::with_capacity(worker_count);
+        for (task_tx, res_rx) in task_txs.iter().zip(res_rxs) {
+            task_tx.send(Task::Finish)
+                .expect("Worker failed");
+
+            let worker = res_rx.recv()
+                .expect("Worker failed");
+            workers.push(worker);
+        }
+        self.pool.join();
+        workers
+    }
+}
+
+/// Way refs, referenced nodes and referenced ways of all workers
+fn merge_references(workers: Vec<References>) -> (Locations, HashSet<u64>, HashSet<u64>) {
+    let mut locations = vec![];
+    let mut nodes = HashSet::new();
+    let mut ways = HashSet::new();
+    for worker in workers {
+        locations.push(worker.locations);
+        nodes.extend(worker.nodes);
+        ways.extend(worker.ways);
+    }
+    (Locations::merge(locations.into_iter()), nodes, ways)
+}
+
+/// State of one worker thread during a scan
+pub trait Worker: Send + 'static {
+    /// Called before the primitives of each blob
+    fn begin_blob(&mut self, _path: &Arc<PathBuf>, _offset: u64) {
+    }
+
+    fn process(&mut self, primitive: &Primitive);
 }
 
+impl Worker for Vec<Processor> {
+    fn process(&mut self, primitive: &Primitive) {
+        // Allocated once, on first match
+        let mut item = None;
+        for processor in self.iter_mut() {
+            if processor.matches(primitive) {
+                let item = item.get_or_insert_with(
+                    || Item::from(primitive)
+                );
+                processor.insert(item.clone());
+            }
+        }
+    }
+}
+
+/// Instantiates a `Processor` per worker thread
 #[derive(Debug, Clone)]
 pub struct ProcessorFactory {
     output: UniqueSet,
-    node: ProcessNode,
-    next_steps: Vec<ProcessorFactory>,
+    target: QueryTarget,
 }
 
 impl ProcessorFactory {
-    pub fn from_process_node(output: UniqueSet, node: ProcessNode) -> Self {
+    pub fn new(output: UniqueSet, target: QueryTarget) -> Self {
         ProcessorFactory {
             output,
-            node,
-            next_steps: vec![],
+            target,
         }
     }
 
-    pub fn add_next_step(&mut self, next: ProcessorFactory) {
-        self.next_steps.push(next);
-    }
-    
     pub fn generate(&self) -> Processor {
-        Processor {}
+        Processor {
+            output: self.output,
+            target: self.target.clone(),
+            results: Set::empty(),
+        }
     }
 }
 
+/// Collects the matching items of one worker
 pub struct Processor {
+    output: UniqueSet,
+    target: QueryTarget,
+    results: Set,
 }
 
 impl Processor {
-    pub fn process(&mut self, item: Item) {
-        // let is_match = self.filters.iter()
-        //     .all(|filter| eval_filter(filter, &item));
-        // if is_match {
-        //     self.results.insert(item);
-        // }
-    }
-
-    pub fn digest(self) -> Set {
-        // TODO:
-        // self.results
-        Set::empty()
+    pub fn matches(&self, primitive: &Primitive) -> bool {
+        self.target.matches(primitive)
+    }
+
+    pub fn insert(&mut self, item: Item) {
+        self.results.insert(item);
+    }
+
+    pub fn digest(self) -> (UniqueSet, Set) {
+        (self.output, self.results)
     }
 }
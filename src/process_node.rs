@@ -1,33 +1,83 @@
-use std::rc::Rc;
 use std::sync::Arc;
-use std::collections::HashMap;
+use std::sync::mpsc::{Sender, Receiver};
+use std::thread::{self, JoinHandle};
+use std::collections::{HashMap, HashSet};
 
-use ql::{Filter, RecurseType};
+use ql::{Filter, RecurseType, SetName, OutputGeometry};
 use trace_node::UniqueSet;
 use set::Set;
-use item::Item;
 use query::QueryTarget;
+use filter::{Context, needs_locations, needs_areas};
 
-#[derive(Debug, PartialEq, Clone)]
+pub enum Message {
+    /// An input set is complete
+    Input(UniqueSet, Arc<Set>),
+    /// Results of the scans for a data query
+    Results(Set),
+}
+
+/// Completed result sets are reported here
+pub type ResultSender = Sender<(UniqueSet, Arc<Set>)>;
+
+/// Runs in its own thread, passing its result set to its targets
+/// when complete
 pub struct ProcessNode {
+    output: UniqueSet,
     process: Process,
-    targets: Vec<Rc<ProcessNode>>,
+    input_sets: HashSet<UniqueSet>,
+    targets: Vec<Sender<Message>>,
 }
 
 impl ProcessNode {
-    pub fn new(process: Process) -> Self {
+    pub fn new(output: UniqueSet, process: Process, input_sets: HashSet<UniqueSet>) -> Self {
         ProcessNode {
+            output,
             process,
+            input_sets,
             targets: vec![],
         }
     }
 
-    pub fn add_target(&mut self, target: Rc<ProcessNode>) {
+    pub fn add_target(&mut self, target: Sender<Message>) {
         self.targets.push(target);
     }
 
-    pub fn targets(&self) -> &[Rc<ProcessNode>] {
-        &self.targets[..]
+    pub fn spawn(self, rx: Receiver<Message>, result_tx: ResultSender) -> JoinHandle<()> {
+        thread::spawn(move || self.run(rx, result_tx))
+    }
+
+    fn run(self, rx: Receiver<Message>, result_tx: ResultSender) {
+        let mut inputs = HashMap::new();
+        loop {
+            // Data queries only wait for their results, other
+            // processes for all inputs
+            if !self.process.is_query() && inputs.len() >= self.input_sets.len() {
+                let results = self.process.evaluate(inputs);
+                self.complete(results, &result_tx);
+                return;
+            }
+
+            match rx.recv() {
+                Ok(Message::Input(input_set, set)) => {
+                    inputs.insert(input_set, set);
+                }
+                Ok(Message::Results(set)) => {
+                    self.complete(Arc::new(set), &result_tx);
+                    return;
+                }
+                Err(_) =>
+                    // Planner gone
+                    return,
+            }
+        }
+    }
+
+    fn complete(&self, results: Arc<Set>, result_tx: &ResultSender) {
+        for target in &self.targets {
+            // Targets only go away when they have failed
+            let _ = target.send(Message::Input(self.output, results.clone()));
+        }
+        let _ = result_tx.send((self.output, results));
     }
 }
 
@@ -36,6 +86,8 @@ pub enum Process {
     /// Data query
     Query {
         filters: Vec<Filter>,
+        /// Sets referenced by filters
+        sets: Vec<(SetName, UniqueSet)>,
     },
     /// Must buffer
     Difference {
@@ -46,26 +98,103 @@ pub enum Process {
     Union,
     /// Data query, with optional index
     Recurse(RecurseType),
-    /// Streaming output
-    Output,
+    /// Lookup in the `AreaStore`
+    IsIn {
+        lat_lon: Option<(f64, f64)>,
+    },
+    /// Passes through its input, which the planner writes in
+    /// statement order
+    Output {
+        geometry: OutputGeometry,
+    },
 }
 
 impl Process {
-    pub fn query_target(&self) -> Option<QueryTarget> {
+    /// Does this process need to scan the data, or the areas
+    /// derived from it?
+    pub fn is_query(&self) -> bool {
+        match self {
+            Process::Query { .. } | Process::Recurse(_) | Process::IsIn { .. } =>
+                true,
+            _ =>
+                false,
+        }
+    }
+
+    pub fn is_output(&self) -> bool {
         match self {
-            Process::Query { filters } => {
+            Process::Output { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Compute the result of a map process from its complete inputs
+    pub fn evaluate(&self, mut inputs: HashMap<UniqueSet, Arc<Set>>) -> Arc<Set> {
+        match self {
+            &Process::Union if inputs.len() == 1 =>
+                inputs.drain()
+                .next()
+                .map(|(_, set)| set)
+                .unwrap(),
+            &Process::Union =>
+                Arc::new(Set::merge(
+                    inputs.into_iter()
+                        .map(|(_, set)| (*set).clone())
+                )),
+            &Process::Difference { ref source, ref remove } =>
+                Arc::new(inputs[source].difference(&inputs[remove])),
+            &Process::Output { .. } =>
+                inputs.drain()
+                .next()
+                .map(|(_, set)| set)
+                .expect("Output without input set"),
+            &Process::Query { .. } | &Process::Recurse(_) | &Process::IsIn { .. } =>
+                panic!("Data query {:?} is not evaluated from inputs", self),
+        }
+    }
+
+    /// Do filters or output need the geometry of ways and relations?
+    pub fn needs_locations(&self) -> bool {
+        match self {
+            Process::Query { filters, .. } =>
+                needs_locations(filters),
+            Process::Output { geometry } =>
+                *geometry != OutputGeometry::None,
+            _ =>
+                false,
+        }
+    }
+
+    /// Does this process use derived areas?
+    pub fn needs_areas(&self) -> bool {
+        match self {
+            Process::Query { filters, .. } =>
+                needs_areas(filters),
+            Process::IsIn { .. } =>
+                true,
+            _ =>
+                false,
+        }
+    }
+
+    /// `context` is completed with input sets provided by `get_set`
+    pub fn query_target<F>(&self, mut context: Context, mut get_set: F) -> Option<QueryTarget>
+    where
+        F: FnMut(&UniqueSet) -> Arc<Set>,
+    {
+        match self {
+            Process::Query { filters, sets } => {
                 let filters = Arc::new(filters.clone());
-                Some(QueryTarget::Query { filters })
+                for &(ref name, ref set) in sets {
+                    context.add_set(name.clone(), get_set(set));
+                }
+                context.prepare_around(&filters);
+                let context = Arc::new(context);
+                Some(QueryTarget::Query { filters, context })
             }
-            Process::Recurse(rt) =>
-                Some(QueryTarget::Recurse(*rt)),
+            // Runs as a `Recursion` or on the `AreaStore`
             _ =>
                 None,
         }
     }
-
-    pub fn process(&mut self, input_set: UniqueSet, item: Item) -> Set {
-        // TODO
-        Set::empty()
-    }
 }
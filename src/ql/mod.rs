@@ -2,7 +2,7 @@ mod statement;
 pub use self::statement::*;
 
 mod parser;
-pub use self::parser::parse;
+pub use self::parser::parse_script;
 
 lalrpop_mod!(pub syntax); // synthesized by LALRPOP
 
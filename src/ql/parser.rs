@@ -1,19 +1,26 @@
 // TODO: delete; use super::tokens::{Token, Tokenizer};
-use super::{SetName, StatementSpec, Statement, QueryType, Filter};
+use super::{SetName, StatementSpec, Statement, QueryType, Filter, Settings};
 use super::syntax::ScriptParser;
 
-pub fn parse(input: &str) -> Vec<StatementSpec> {
+pub fn parse_script(input: &str) -> (Settings, Vec<StatementSpec>) {
     // TODO: propagate input sets across composite statements
     ScriptParser::new()
         .parse(input)
         .unwrap()
 }
 
+/// Statements without settings
+#[cfg(test)]
+pub fn parse(input: &str) -> Vec<StatementSpec> {
+    parse_script(input).1
+}
+
 
 #[cfg(test)]
 mod tests {
-    use super::super::{SetName, StatementSpec, Statement, RecurseType, Filter, QueryType, TagSpec};
-    use super::parse;
+    use super::super::{SetName, StatementSpec, Statement, RecurseType, Filter, QueryType, TagSpec, OutputGeometry, Evaluator, CompareOp, OutputFormat, CsvFormat, CsvColumn};
+    use bbox::BoundingBox;
+    use super::{parse, parse_script};
 
     #[test]
     fn test_empty_union() {
@@ -315,6 +322,174 @@ nwr (5)
         );
     }
 
+    #[test]
+    fn test_query_filter_bbox_antimeridian() {
+        assert_eq!(parse("node(-21, 177, -12.5, -178);"), vec![StatementSpec {
+            inputs: vec![],
+            statement: Statement::Query {
+                filters: vec![
+                    Filter::QueryType(QueryType::Node),
+                    Filter::BoundingBox { s: -21.0, w: 177.0, n: -12.5, e: -178.0 },
+                ],
+            },
+            output: SetName::default(),
+        }]);
+    }
+
+    #[test]
+    fn test_query_filter_area() {
+        assert_eq!(parse("node(area); way(area.a); nwr(area:3600062422);"), vec![
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query {
+                    filters: vec![
+                        Filter::QueryType(QueryType::Node),
+                        Filter::Area(SetName::default()),
+                    ],
+                },
+                output: SetName::default(),
+            },
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query {
+                    filters: vec![
+                        Filter::QueryType(QueryType::Way),
+                        Filter::Area(SetName::from("a".to_string())),
+                    ],
+                },
+                output: SetName::default(),
+            },
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query {
+                    filters: vec![
+                        Filter::QueryType(QueryType::NWR),
+                        Filter::AreaId(3600062422),
+                    ],
+                },
+                output: SetName::default(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_query_filter_around() {
+        let node = |filter| vec![StatementSpec {
+            inputs: vec![],
+            statement: Statement::Query {
+                filters: vec![Filter::QueryType(QueryType::Node), filter],
+            },
+            output: SetName::default(),
+        }];
+        assert_eq!(parse("node(around:50,51.08,13.73);"), node(Filter::Around {
+            radius: 50.0,
+            line: vec![(51.08, 13.73)],
+        }));
+        assert_eq!(parse("node(around:12.5,51.0,13.0,51.5,13.5);"), node(Filter::Around {
+            radius: 12.5,
+            line: vec![(51.0, 13.0), (51.5, 13.5)],
+        }));
+        assert_eq!(parse("node(around.a:100);"), node(Filter::AroundSet {
+            name: SetName::from("a".to_string()),
+            radius: 100.0,
+        }));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_query_filter_around_odd() {
+        parse("node(around:50,51.08);");
+    }
+
+    #[test]
+    fn test_query_filter_poly() {
+        let statements = parse("way(poly:\"51.0 13.0 51.0 14.0 52.0 14.0\");");
+        match statements[0].statement {
+            Statement::Query { ref filters } => match filters[1] {
+                Filter::Polygon(ref multipolygon) =>
+                    assert_eq!(multipolygon.polygons[0].outer.points, vec![
+                        (51.0, 13.0), (51.0, 14.0), (52.0, 14.0), (51.0, 13.0),
+                    ]),
+                ref filter =>
+                    panic!("Not a polygon: {:?}", filter),
+            },
+            ref statement =>
+                panic!("Not a query: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_query_filter_if() {
+        let statements = parse("way(if: length() > 1000 && !(area() <= 500) || 0);");
+        match statements[0].statement {
+            Statement::Query { ref filters } =>
+                assert_eq!(filters[1], Filter::If(Evaluator::Or(
+                    Box::new(Evaluator::And(
+                        Box::new(Evaluator::Compare(
+                            Box::new(Evaluator::Length),
+                            CompareOp::Greater,
+                            Box::new(Evaluator::Number(1000.0))
+                        )),
+                        Box::new(Evaluator::Not(Box::new(Evaluator::Compare(
+                            Box::new(Evaluator::Area),
+                            CompareOp::LessEqual,
+                            Box::new(Evaluator::Number(500.0))
+                        )))),
+                    )),
+                    Box::new(Evaluator::Number(0.0))
+                ))),
+            ref statement =>
+                panic!("Not a query: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_settings() {
+        let (settings, statements) = parse_script("[out:json][timeout:25]; node; out;");
+        assert_eq!(settings.output_format, OutputFormat::Json);
+        assert_eq!(statements.len(), 2);
+        let (settings, _) = parse_script("[out:geojson]; node; out;");
+        assert_eq!(settings.output_format, OutputFormat::GeoJson);
+        let (settings, _) = parse_script("[out:pbf]; node; out;");
+        assert_eq!(settings.output_format, OutputFormat::Pbf);
+        let (settings, _) = parse_script("node; out;");
+        assert_eq!(settings.output_format, OutputFormat::Xml);
+    }
+
+    #[test]
+    fn test_settings_csv() {
+        let (settings, _) = parse_script("[out:csv(::id, ::type, ::lat, ::lon, ::length, name, \"addr:street\"; false; \",\")]; node; out;");
+        assert_eq!(settings.output_format, OutputFormat::Csv(CsvFormat {
+            columns: vec![
+                CsvColumn::Id, CsvColumn::Type, CsvColumn::Lat, CsvColumn::Lon, CsvColumn::Length,
+                CsvColumn::Tag("name".to_owned()), CsvColumn::Tag("addr:street".to_owned()),
+            ],
+            header: false,
+            separator: ",".to_owned(),
+        }));
+        let (settings, _) = parse_script("[out:csv(name)]; node; out;");
+        assert_eq!(settings.output_format, OutputFormat::Csv(CsvFormat {
+            columns: vec![CsvColumn::Tag("name".to_owned())],
+            header: true,
+            separator: "\t".to_owned(),
+        }));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_settings_unknown_format() {
+        parse_script("[out:yaml]; node; out;");
+    }
+
+    #[test]
+    fn test_add_filter() {
+        let mut statements = parse("( node; - way; ); area[name=Dresden];");
+        for statement_spec in statements.iter_mut() {
+            statement_spec.add_filter(&Filter::Id(1));
+        }
+        assert_eq!(statements, parse("( node(1); - way(1); ); area[name=Dresden];"));
+    }
+
     #[test]
     fn test_recurse() {
         assert_eq!(parse("<; .a <<; > -> .b; .a >> -> .b;"), vec![
@@ -341,11 +516,32 @@ nwr (5)
         ]);
     }
 
+    #[test]
+    fn test_is_in() {
+        assert_eq!(parse("is_in; .a is_in -> .b; is_in(51.08, 13.73);"), vec![
+            StatementSpec {
+                inputs: vec![SetName::default()],
+                statement: Statement::IsInArea { lat_lon: None },
+                output: SetName::default(),
+            },
+            StatementSpec {
+                inputs: vec![SetName::from("a".to_string())],
+                statement: Statement::IsInArea { lat_lon: None },
+                output: SetName::from("b".to_string()),
+            },
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::IsInArea { lat_lon: Some((51.08, 13.73)) },
+                output: SetName::default(),
+            },
+        ]);
+    }
+
     #[test]
     fn test_output() {
         assert_eq!(parse("out;"), vec![StatementSpec {
             inputs: vec![SetName::default()],
-            statement: Statement::Output,
+            statement: Statement::Output { geometry: OutputGeometry::None },
             output: SetName::default(),
         }]);
     }
@@ -354,11 +550,26 @@ nwr (5)
     fn test_output_named_input() {
         assert_eq!(parse(".test out;"), vec![StatementSpec {
             inputs: vec![SetName::from("test".to_string())],
-            statement: Statement::Output,
+            statement: Statement::Output { geometry: OutputGeometry::None },
             output: SetName::default(),
         }]);
     }
 
+    #[test]
+    fn test_output_geometry() {
+        let output = |geometry| StatementSpec {
+            inputs: vec![SetName::default()],
+            statement: Statement::Output { geometry },
+            output: SetName::default(),
+        };
+        assert_eq!(parse("out geom; out geom(51, 13.5, 52, 14); out center; out bb;"), vec![
+            output(OutputGeometry::Geom { clip: None }),
+            output(OutputGeometry::Geom { clip: Some(BoundingBox::new(51.0, 13.5, 52.0, 14.0)) }),
+            output(OutputGeometry::Center),
+            output(OutputGeometry::Bounds),
+        ]);
+    }
+
     #[test]
     fn test_output_many_statements() {
         assert_eq!(parse("node->.m; .m->.n; .n out;"), vec![
@@ -378,7 +589,7 @@ nwr (5)
             },
             StatementSpec {
                 inputs: vec![SetName::from("n".to_string())],
-                statement: Statement::Output,
+                statement: Statement::Output { geometry: OutputGeometry::None },
                 output: SetName::default(),
             },
         ]);
@@ -1,5 +1,8 @@
 use regex::{Regex, RegexBuilder};
 
+use geometry::MultiPolygon;
+use bbox::BoundingBox;
+
 #[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub struct SetName(String);
 
@@ -22,13 +25,46 @@ pub struct StatementSpec {
     pub output: SetName,
 }
 
+impl StatementSpec {
+    /// Restrict all data queries, including those nested in
+    /// composite statements
+    pub fn add_filter(&mut self, filter: &Filter) {
+        match self.statement {
+            Statement::Query { ref mut filters } => {
+                let is_derived = filters.iter()
+                    .any(|filter| match filter {
+                        &Filter::QueryType(QueryType::Area) |
+                        &Filter::QueryType(QueryType::Derived) => true,
+                        _ => false,
+                    });
+                if !is_derived {
+                    filters.push(filter.clone());
+                }
+            }
+            Statement::Union { ref mut members } =>
+                for member in members.iter_mut() {
+                    member.add_filter(filter);
+                },
+            Statement::Difference { ref mut source, ref mut remove } => {
+                source.add_filter(filter);
+                remove.add_filter(filter);
+            }
+            _ => (),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Statement {
     Query {
         filters: Vec<Filter>,
     },
     Recurse(RecurseType),
-    IsInArea,
+    /// Areas containing the nodes of the input set, or the given
+    /// coordinate
+    IsInArea {
+        lat_lon: Option<(f64, f64)>,
+    },
     Union {
         members: Vec<StatementSpec>,
     },
@@ -38,7 +74,90 @@ pub enum Statement {
     },
     /// Source from a set
     Item,
-    Output,
+    Output {
+        geometry: OutputGeometry,
+    },
+}
+
+/// Coordinates added to ways and relations by `out`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputGeometry {
+    None,
+    /// `out geom`, optionally clipped: `out geom(s,w,n,e)`
+    Geom {
+        clip: Option<BoundingBox>,
+    },
+    /// `out center`
+    Center,
+    /// `out bb`
+    Bounds,
+}
+
+/// Global settings at the start of a script: `[out:json];`
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Settings {
+    pub output_format: OutputFormat,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum OutputFormat {
+    /// OSM XML, the default
+    Xml,
+    /// Overpass JSON
+    Json,
+    /// GeoJSON features with assembled geometry
+    GeoJson,
+    /// `[out:csv(::id, name; true; ",")]`
+    Csv(CsvFormat),
+    /// OSM PBF, for further processing by other tools
+    Pbf,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Xml
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CsvFormat {
+    pub columns: Vec<CsvColumn>,
+    /// Print a header line
+    pub header: bool,
+    pub separator: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CsvColumn {
+    Tag(String),
+    /// `::id`
+    Id,
+    /// `::type`
+    Type,
+    /// `::lat`, of the center for ways and relations with `out center`
+    Lat,
+    /// `::lon`
+    Lon,
+    /// `::length` in meters
+    Length,
+    /// `::area` in square meters
+    Area,
+}
+
+impl CsvColumn {
+    /// Special fields are named with `@` in the header, like in
+    /// Overpass
+    pub fn name(&self) -> &str {
+        match self {
+            &CsvColumn::Tag(ref key) => key,
+            &CsvColumn::Id => "@id",
+            &CsvColumn::Type => "@type",
+            &CsvColumn::Lat => "@lat",
+            &CsvColumn::Lon => "@lon",
+            &CsvColumn::Length => "@length",
+            &CsvColumn::Area => "@area",
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
@@ -63,6 +182,8 @@ pub enum QueryType {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Filter {
     QueryType(QueryType),
+    /// Ids are per type: `node(42)` and `way(42)` are distinct,
+    /// `nwr(42)` matches both.
     Id(u64),
     BoundingBox {
         s: f64,
@@ -85,12 +206,103 @@ pub enum Filter {
         k: TagSpec,
     },
     Intersection(SetName),
+    /// Inside the areas of a set: `(area)`, `(area.a)`
+    Area(SetName),
+    /// Inside a derived area by id: `(area:3600062422)`
+    AreaId(u64),
+    /// Within `radius` meters of a point or polyline:
+    /// `(around:50,51.08,13.73)`
+    Around {
+        radius: f64,
+        line: Vec<(f64, f64)>,
+    },
+    /// Inside a polygon: `(poly:"lat lon lat lon ...")`
+    Polygon(MultiPolygon),
+    /// Evaluator must be non-zero: `(if: length() > 1000)`
+    If(Evaluator),
+    /// Within `radius` meters of the elements of a set:
+    /// `(around.a:50)`
+    AroundSet {
+        name: SetName,
+        radius: f64,
+    },
     // Recurse {
     //     recurse_target: (),
     //     input: SetName,
     // },
 }
 
+/// Expressions of `if:` filters, evaluated to numbers. Comparisons
+/// and logic yield 1 or 0.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Evaluator {
+    Number(f64),
+    /// `length()` in meters
+    Length,
+    /// `area()` in square meters
+    Area,
+    Compare(Box<Evaluator>, CompareOp, Box<Evaluator>),
+    And(Box<Evaluator>, Box<Evaluator>),
+    Or(Box<Evaluator>, Box<Evaluator>),
+    Not(Box<Evaluator>),
+}
+
+impl Evaluator {
+    /// Does it measure geometry?
+    pub fn needs_locations(&self) -> bool {
+        match self {
+            Evaluator::Number(_) =>
+                false,
+            Evaluator::Length |
+            Evaluator::Area =>
+                true,
+            Evaluator::Compare(a, _, b) |
+            Evaluator::And(a, b) |
+            Evaluator::Or(a, b) =>
+                a.needs_locations() || b.needs_locations(),
+            Evaluator::Not(a) =>
+                a.needs_locations(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CompareOp {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl CompareOp {
+    pub fn test(&self, a: f64, b: f64) -> bool {
+        match self {
+            CompareOp::Less => a < b,
+            CompareOp::LessEqual => a <= b,
+            CompareOp::Greater => a > b,
+            CompareOp::GreaterEqual => a >= b,
+            CompareOp::Equal => a == b,
+            CompareOp::NotEqual => a != b,
+        }
+    }
+}
+
+impl Filter {
+    /// Name of a set that must be evaluated before this filter
+    pub fn input_set(&self) -> Option<&SetName> {
+        match self {
+            Filter::Intersection(name) |
+            Filter::Area(name) |
+            Filter::AroundSet { name, .. } =>
+                Some(name),
+            _ =>
+                None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum TagSpec {
     String(String),
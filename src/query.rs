@@ -5,19 +5,20 @@ use std::sync::mpsc::sync_channel;
 use threadpool::{self, ThreadPool};
 use osm_pbf_iter::{Primitive, PrimitiveBlock, Blob};
 
-use item::Item;
+use item::{Item, ItemId};
 use set::Set;
 use pbf_source::PbfSource;
 use ql::{Statement, Filter, TagSpec, RecurseType, QueryType};
-use filter::eval_filter;
+use filter::{eval_filter, Context};
 use trace_node::UniqueSet;
 use process_node::ProcessNode;
+use recurse::is_recurse_match;
+use element::Element;
 
 enum Task {
     Blob(Blob),
     Finish,
 }
-unsafe impl Send for Task {}
 
 pub struct QueryRunner {
     source: PbfSource,
@@ -123,9 +124,38 @@ impl QueryRunner {
     // }
 }
 
+#[derive(Debug, Clone)]
 pub enum QueryTarget {
     Query {
         filters: Arc<Vec<Filter>>,
+        context: Arc<Context>,
     },
-    Recurse(RecurseType),
+    /// One step of a `Recursion`
+    Recurse {
+        recurse_type: RecurseType,
+        ids: Arc<HashSet<ItemId>>,
+    },
+}
+
+impl QueryTarget {
+    /// Areas are not in the data but in the `AreaStore`
+    pub fn only_areas(&self) -> bool {
+        match self {
+            QueryTarget::Query { filters, .. } =>
+                filters.contains(&Filter::QueryType(QueryType::Area)),
+            QueryTarget::Recurse { .. } =>
+                false,
+        }
+    }
+
+    /// Does the item belong into the result set?
+    pub fn matches<E: Element>(&self, item: &E) -> bool {
+        match self {
+            QueryTarget::Query { filters, context } =>
+                filters.iter()
+                .all(|filter| eval_filter(filter, item, context)),
+            QueryTarget::Recurse { recurse_type, ids } =>
+                is_recurse_match(*recurse_type, ids, item),
+        }
+    }
 }
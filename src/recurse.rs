@@ -0,0 +1,213 @@
+use std::sync::Arc;
+use std::collections::HashSet;
+
+use item::{Item, ItemId, ItemType};
+use set::Set;
+use ql::RecurseType;
+use query::QueryTarget;
+use element::Element;
+
+/// Evaluates a recurse statement. Because elements may refer to
+/// others anywhere in the data, each step of the recursion takes
+/// another scan.
+pub struct Recursion {
+    recurse_type: RecurseType,
+    /// What to look for in the next scan: members for down, children
+    /// for up recursion
+    ids: HashSet<ItemId>,
+    results: Set,
+}
+
+impl Recursion {
+    pub fn new(recurse_type: RecurseType, input: &Set) -> Self {
+        let mut recursion = Recursion {
+            recurse_type,
+            ids: HashSet::new(),
+            results: Set::empty(),
+        };
+        recursion.ids = recursion.next_ids(input.iter());
+        recursion
+    }
+
+    /// `None` once the recursion is complete
+    pub fn query_target(&self) -> Option<QueryTarget> {
+        if self.ids.is_empty() {
+            None
+        } else {
+            Some(QueryTarget::Recurse {
+                recurse_type: self.recurse_type,
+                ids: Arc::new(self.ids.clone()),
+            })
+        }
+    }
+
+    /// Take the results of a scan
+    pub fn advance(&mut self, found: Set) {
+        let new_items = found.into_iter()
+            .filter(|item| !self.results.contains(item))
+            .collect::<Vec<_>>();
+        self.ids = self.next_ids(new_items.iter());
+        for item in new_items {
+            self.results.insert(item);
+        }
+    }
+
+    pub fn into_results(self) -> Set {
+        self.results
+    }
+
+    fn next_ids<'a, I>(&self, items: I) -> HashSet<ItemId>
+    where
+        I: Iterator<Item=&'a Item>,
+    {
+        match self.recurse_type {
+            RecurseType::Down | RecurseType::DownRelations =>
+                items.flat_map(|item| {
+                    let is_way = item.is_way();
+                    item.member_ids().into_iter()
+                        // Ways only have nodes
+                        .filter(move |id| is_way || self.follows(id.item_type))
+                }).filter(|id| !self.results.contains_id(id))
+                .collect(),
+            RecurseType::Up | RecurseType::UpRelations =>
+                items.map(|item| item.item_id())
+                .filter(|id| self.follows(id.item_type))
+                .collect(),
+        }
+    }
+
+    /// `>` and `<` reach only nodes and ways as relation members,
+    /// `>>` and `<<` reach relations, too.
+    fn follows(&self, item_type: ItemType) -> bool {
+        match item_type {
+            ItemType::Node | ItemType::Way =>
+                true,
+            ItemType::Relation =>
+                self.recurse_type == RecurseType::DownRelations ||
+                self.recurse_type == RecurseType::UpRelations,
+            _ =>
+                false,
+        }
+    }
+}
+
+/// Evaluated during a scan
+pub fn is_recurse_match<E: Element>(recurse_type: RecurseType, ids: &HashSet<ItemId>, item: &E) -> bool {
+    match recurse_type {
+        RecurseType::Down | RecurseType::DownRelations =>
+            ids.contains(&item.item_id()),
+        RecurseType::Up | RecurseType::UpRelations =>
+            item.any_member(|id| ids.contains(id)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use ql::RecurseType;
+    use super::Recursion;
+
+    /// n1 - n2 - n3 form way w10, n4 is standalone. Relation r20 has
+    /// w10 and n4 as members, relation r30 has r20.
+    fn data() -> Vec<Item> {
+        let node = |id| Item::new(id, HashMap::new(), ItemSpecific::Node {
+            lat: 0.0, lon: 0.0,
+        });
+        vec![
+            node(1), node(2), node(3), node(4),
+            Item::new(10, HashMap::new(), ItemSpecific::Way {
+                refs: vec![1, 2, 3],
+            }),
+            Item::new(20, HashMap::new(), ItemSpecific::Relation {
+                members: vec![
+                    ("outer".to_owned(), ItemId::new(ItemType::Way, 10)),
+                    ("".to_owned(), ItemId::new(ItemType::Node, 4)),
+                ],
+            }),
+            Item::new(30, HashMap::new(), ItemSpecific::Relation {
+                members: vec![
+                    ("subarea".to_owned(), ItemId::new(ItemType::Relation, 20)),
+                ],
+            }),
+        ]
+    }
+
+    fn run(recurse_type: RecurseType, input: &[ItemId]) -> Vec<ItemId> {
+        let data = data();
+        let mut input_set = Set::empty();
+        for item in data.iter() {
+            if input.contains(&item.item_id()) {
+                input_set.insert(item.clone());
+            }
+        }
+
+        let mut recursion = Recursion::new(recurse_type, &input_set);
+        let mut scans = 0;
+        while let Some(target) = recursion.query_target() {
+            let mut found = Set::empty();
+            for item in data.iter() {
+                if target.matches(item) {
+                    found.insert(item.clone());
+                }
+            }
+            recursion.advance(found);
+            scans += 1;
+            assert!(scans < 10);
+        }
+        let mut results = recursion.into_results()
+            .into_iter()
+            .map(|item| item.item_id())
+            .collect::<Vec<_>>();
+        results.sort();
+        results
+    }
+
+    fn ids(item_type: ItemType, ids: &[u64]) -> Vec<ItemId> {
+        ids.iter()
+            .map(|id| ItemId::new(item_type, *id))
+            .collect()
+    }
+
+    #[test]
+    fn test_down() {
+        assert_eq!(run(RecurseType::Down, &ids(ItemType::Way, &[10])),
+                   ids(ItemType::Node, &[1, 2, 3]));
+        let mut expected = ids(ItemType::Node, &[1, 2, 3, 4]);
+        expected.extend(ids(ItemType::Way, &[10]));
+        assert_eq!(run(RecurseType::Down, &ids(ItemType::Relation, &[20])),
+                   expected);
+        // Relation members are not followed
+        assert_eq!(run(RecurseType::Down, &ids(ItemType::Relation, &[30])),
+                   vec![]);
+    }
+
+    #[test]
+    fn test_down_relations() {
+        let mut expected = ids(ItemType::Node, &[1, 2, 3, 4]);
+        expected.extend(ids(ItemType::Way, &[10]));
+        expected.extend(ids(ItemType::Relation, &[20]));
+        assert_eq!(run(RecurseType::DownRelations, &ids(ItemType::Relation, &[30])),
+                   expected);
+    }
+
+    #[test]
+    fn test_up() {
+        let mut expected = ids(ItemType::Way, &[10]);
+        expected.extend(ids(ItemType::Relation, &[20]));
+        assert_eq!(run(RecurseType::Up, &ids(ItemType::Node, &[2])),
+                   expected);
+        assert_eq!(run(RecurseType::Up, &ids(ItemType::Node, &[4])),
+                   ids(ItemType::Relation, &[20]));
+    }
+
+    #[test]
+    fn test_up_relations() {
+        let mut expected = ids(ItemType::Way, &[10]);
+        expected.extend(ids(ItemType::Relation, &[20, 30]));
+        assert_eq!(run(RecurseType::UpRelations, &ids(ItemType::Node, &[2])),
+                   expected);
+    }
+}
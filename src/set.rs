@@ -1,8 +1,10 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::collections::hash_map;
+use std::iter;
 
-use item::Item;
+use item::{Item, ItemId};
 
-type Contents = HashSet<Item>;
+type Contents = HashMap<ItemId, Item>;
 
 #[derive(Debug, Clone)]
 pub struct Set {
@@ -11,7 +13,7 @@ pub struct Set {
 
 impl Set {
     pub fn empty() -> Self {
-        Set { contents: HashSet::new() }
+        Set { contents: HashMap::new() }
     }
 
     pub fn new(contents: Contents) -> Self {
@@ -25,18 +27,86 @@ impl Set {
         let mut contents = match sets.next() {
             None =>
                 // Empty case
-                HashSet::new(),
+                HashMap::new(),
             Some(set) => set.contents,
         };
         for set in sets.into_iter() {
-            for item in set.contents {
-                contents.insert(item);
+            for (id, item) in set.contents {
+                contents.insert(id, item);
             }
         }
         Set { contents }
     }
 
     pub fn insert(&mut self, item: Item) {
-        self.contents.insert(item);
+        self.contents.insert(item.item_id(), item);
+    }
+
+    pub fn contains(&self, item: &Item) -> bool {
+        self.contains_id(&item.item_id())
+    }
+
+    pub fn contains_id(&self, id: &ItemId) -> bool {
+        self.contents.contains_key(id)
+    }
+
+    pub fn get(&self, id: &ItemId) -> Option<&Item> {
+        self.contents.get(id)
+    }
+
+    /// Items not contained in `other`
+    pub fn difference(&self, other: &Set) -> Set {
+        let contents = self.contents.iter()
+            .filter(|&(id, _)| !other.contains_id(id))
+            .map(|(id, item)| (*id, item.clone()))
+            .collect();
+        Set { contents }
+    }
+
+    pub fn len(&self) -> usize {
+        self.contents.len()
+    }
+
+    pub fn iter(&self) -> hash_map::Values<ItemId, Item> {
+        self.contents.values()
+    }
+}
+
+impl IntoIterator for Set {
+    type Item = Item;
+    type IntoIter = iter::Map<hash_map::IntoIter<ItemId, Item>, fn((ItemId, Item)) -> Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        fn item((_, item): (ItemId, Item)) -> Item {
+            item
+        }
+        self.contents.into_iter()
+            .map(item)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use super::Set;
+
+    #[test]
+    fn test_merge_same_id_different_types() {
+        let mut nodes = Set::empty();
+        nodes.insert(Item::new(42, HashMap::new(), ItemSpecific::Node {
+            lat: 51.0, lon: 13.7,
+        }));
+        let mut ways = Set::empty();
+        ways.insert(Item::new(42, HashMap::new(), ItemSpecific::Way {
+            refs: vec![1, 2],
+        }));
+
+        let merged = Set::merge(vec![nodes, ways].into_iter());
+        assert_eq!(merged.len(), 2);
+        assert!(merged.contains_id(&ItemId::new(ItemType::Node, 42)));
+        assert!(merged.contains_id(&ItemId::new(ItemType::Way, 42)));
+        assert!(!merged.contains_id(&ItemId::new(ItemType::Relation, 42)));
     }
 }
@@ -0,0 +1,529 @@
+use std::fs::File;
+use std::io::{self, Write, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet, BTreeSet};
+use osm_pbf_iter::Primitive;
+
+use item::{ItemId, ItemType};
+use bbox::BoundingBox;
+use locations::Locations;
+use pbf_source::PbfSource;
+use process::{Runner, Worker};
+use query::QueryTarget;
+use trace_node::UniqueSet;
+use ql::{Filter, QueryType, RecurseType};
+use binary::*;
+
+/// Size of the grid cells in degrees
+const CELL_SIZE: f64 = 0.5;
+
+const MAGIC: &[u8; 8] = b"UTINDEX1";
+
+type Cell = (i32, i32);
+
+/// The 90° and 180° edges belong to the last row and column
+fn cell(lat: f64, lon: f64) -> Cell {
+    let max_lat = (90.0 / CELL_SIZE) as i32 - 1;
+    let max_lon = (180.0 / CELL_SIZE) as i32 - 1;
+    (((lat / CELL_SIZE).floor() as i32).min(max_lat),
+     ((lon / CELL_SIZE).floor() as i32).min(max_lon))
+}
+
+/// Cells covered by a bounding box, on both sides of the 180°
+/// meridian if it wraps
+fn bbox_cells(bbox: &BoundingBox) -> Vec<Cell> {
+    let mut cells = vec![];
+    for part in bbox.split() {
+        let (s, w) = cell(part.s, part.w);
+        let (n, e) = cell(part.n, part.e);
+        for lat in s..(n + 1) {
+            for lon in w..(e + 1) {
+                cells.push((lat, lon));
+            }
+        }
+    }
+    cells
+}
+
+fn type_index(item_type: ItemType) -> Option<usize> {
+    match item_type {
+        ItemType::Node => Some(0),
+        ItemType::Way => Some(1),
+        ItemType::Relation => Some(2),
+        _ => None,
+    }
+}
+
+/// What is in a blob
+#[derive(Debug, PartialEq, Clone)]
+pub struct BlobEntry {
+    pub offset: u64,
+    /// Cells touched by nodes, by bounding boxes of ways, and by
+    /// node and way members of relations. Sorted.
+    pub cells: Vec<Cell>,
+    /// Lowest and highest id of nodes, ways and relations
+    pub ids: [Option<(u64, u64)>; 3],
+}
+
+impl BlobEntry {
+    fn new(offset: u64) -> Self {
+        BlobEntry {
+            offset,
+            cells: vec![],
+            ids: [None; 3],
+        }
+    }
+
+    fn add_id(&mut self, item_id: ItemId) {
+        if let Some(i) = type_index(item_id.item_type) {
+            let id = item_id.id;
+            self.ids[i] = Some(match self.ids[i] {
+                None => (id, id),
+                Some((min, max)) => (min.min(id), max.max(id)),
+            });
+        }
+    }
+
+    /// Are any of the ids in the ranges of this blob?
+    fn may_contain_any(&self, ids: &BTreeSet<ItemId>) -> bool {
+        [ItemType::Node, ItemType::Way, ItemType::Relation].iter()
+            .zip(self.ids.iter())
+            .any(|(item_type, range)| match range {
+                &Some((min, max)) =>
+                    ids.range(ItemId::new(*item_type, min)..=ItemId::new(*item_type, max))
+                    .next()
+                    .is_some(),
+                &None =>
+                    false,
+            })
+    }
+}
+
+/// Maps regions and element ids to the blobs of the PBF files.
+/// Persisted next to each PBF file.
+#[derive(Debug)]
+pub struct SpatialIndex {
+    files: Vec<(Arc<PathBuf>, Vec<BlobEntry>)>,
+}
+
+impl SpatialIndex {
+    /// Builds with an additional scan for locations
+    pub fn build(runner: &Runner) -> Self {
+        eprintln!("Collecting locations");
+        let locations = Arc::new(runner.collect_locations(None, None));
+        eprintln!("Indexing");
+        let workers = runner.scan_all(move || IndexWorker {
+            locations: locations.clone(),
+            current: None,
+            entries: vec![],
+        });
+
+        let mut files = HashMap::new();
+        for worker in workers {
+            for (path, entry) in worker.into_entries() {
+                files.entry(path)
+                    .or_insert_with(Vec::new)
+                    .push(entry);
+            }
+        }
+        let files = runner.source().paths().iter()
+            .map(|path| {
+                let mut entries = files.remove(path)
+                    .unwrap_or_else(Vec::new);
+                entries.sort_by_key(|entry| entry.offset);
+                (path.clone(), entries)
+            }).collect();
+        SpatialIndex { files }
+    }
+
+    /// `None` unless there are index files that are newer than their
+    /// PBF files
+    pub fn open(source: &PbfSource) -> Option<Self> {
+        let mut files = vec![];
+        for path in source.paths() {
+            let index_path = sidecar_path(path, "index");
+            if !is_fresh(&index_path, Some(path.as_path())) {
+                return None;
+            }
+            match load_entries(&index_path) {
+                Ok(entries) =>
+                    files.push((path.clone(), entries)),
+                Err(e) => {
+                    eprintln!("Cannot load {}: {}", index_path.display(), e);
+                    return None;
+                }
+            }
+        }
+        Some(SpatialIndex { files })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        for &(ref path, ref entries) in self.files.iter() {
+            save_entries(&sidecar_path(path, "index"), entries)?;
+        }
+        Ok(())
+    }
+
+    /// Blobs that may contain matches for any of the targets. `None`
+    /// if a target may match anywhere.
+    pub fn select(&self, targets: &[(UniqueSet, QueryTarget)]) -> Option<Vec<(Arc<PathBuf>, u64)>> {
+        let constraints = targets.iter()
+            .map(|&(_, ref target)| Constraint::new(target))
+            .collect::<Option<Vec<_>>>()?;
+        let blobs = self.files.iter()
+            .flat_map(|&(ref path, ref entries)|
+                entries.iter()
+                    .filter(|entry| constraints.iter().any(|constraint| constraint.may_match(entry)))
+                    .map(move |entry| (path.clone(), entry.offset))
+            ).collect();
+        Some(blobs)
+    }
+
+    /// Blobs with the geometry of all that the queries may match:
+    /// those `select()` would read for them, and the node and way
+    /// blobs sharing a cell with these. `None` if a query may match
+    /// anywhere.
+    pub fn select_locations(&self, queries: &[&[Filter]]) -> Option<Vec<(Arc<PathBuf>, u64)>> {
+        let constraints = queries.iter()
+            .map(|filters| Constraint::from_filters(filters))
+            .collect::<Option<Vec<_>>>()?;
+        let may_match = |entry: &BlobEntry|
+            constraints.iter().any(|constraint| constraint.may_match(entry));
+        let cells = self.files.iter()
+            .flat_map(|&(_, ref entries)| entries.iter())
+            .filter(|entry| may_match(entry))
+            .flat_map(|entry| entry.cells.iter().cloned())
+            .collect::<HashSet<_>>();
+        let blobs = self.files.iter()
+            .flat_map(|&(ref path, ref entries)|
+                entries.iter()
+                    .filter(|entry| may_match(entry) || (
+                        (entry.ids[0].is_some() || entry.ids[1].is_some()) &&
+                            entry.cells.iter().any(|cell| cells.contains(cell))
+                    ))
+                    .map(move |entry| (path.clone(), entry.offset))
+            ).collect();
+        Some(blobs)
+    }
+}
+
+/// What the index can tell about a query target
+struct Constraint {
+    /// Node, way, relation
+    types: [bool; 3],
+    /// Any of these
+    ids: Option<BTreeSet<ItemId>>,
+    /// Any of these
+    cells: Option<HashSet<Cell>>,
+}
+
+impl Constraint {
+    /// `None` if the target may match anywhere
+    fn new(target: &QueryTarget) -> Option<Self> {
+        let filters = match target {
+            QueryTarget::Query { filters, .. } =>
+                filters,
+            QueryTarget::Recurse { recurse_type: RecurseType::Down, ids } |
+            QueryTarget::Recurse { recurse_type: RecurseType::DownRelations, ids } =>
+                return Some(Constraint {
+                    types: [true; 3],
+                    ids: Some(ids.iter().cloned().collect()),
+                    cells: None,
+                }),
+            // Looks for members anywhere
+            QueryTarget::Recurse { .. } =>
+                return None,
+        };
+        Constraint::from_filters(filters)
+    }
+
+    /// `None` if the query may match anywhere
+    fn from_filters(filters: &[Filter]) -> Option<Self> {
+        let mut types = [true; 3];
+        let mut id = None;
+        let mut cells: Option<HashSet<Cell>> = None;
+        for filter in filters.iter() {
+            let filter_cells = match filter {
+                &Filter::QueryType(query_type) => {
+                    types = match query_type {
+                        QueryType::Node => [true, false, false],
+                        QueryType::Way => [false, true, false],
+                        QueryType::Relation => [false, false, true],
+                        QueryType::NWR => [true; 3],
+                        // Not in the data
+                        QueryType::Area | QueryType::Derived => [false; 3],
+                    };
+                    None
+                }
+                &Filter::Id(filter_id) => {
+                    id = Some(filter_id);
+                    None
+                }
+                &Filter::BoundingBox { s, w, n, e } =>
+                    Some(bbox_cells(&BoundingBox::new(s, w, n, e))),
+                &Filter::Polygon(ref multipolygon) =>
+                    multipolygon.bbox()
+                    .map(|bbox| bbox_cells(&bbox)),
+                &Filter::Around { radius, ref line } =>
+                    BoundingBox::planar_from_points(line.iter().cloned())
+                    .map(|bbox| bbox_cells(&bbox.expand(radius))),
+                _ =>
+                    None,
+            };
+            if let Some(filter_cells) = filter_cells {
+                let filter_cells = filter_cells.into_iter().collect::<HashSet<_>>();
+                // Filters must all match
+                cells = Some(match cells {
+                    None => filter_cells,
+                    Some(cells) => cells.intersection(&filter_cells).cloned().collect(),
+                });
+            }
+        }
+
+        let ids = id.map(|id|
+            [ItemType::Node, ItemType::Way, ItemType::Relation].iter()
+                .enumerate()
+                .filter(|&(i, _)| types[i])
+                .map(|(_, item_type)| ItemId::new(*item_type, id))
+                .collect()
+        );
+        if types == [true; 3] && ids.is_none() && cells.is_none() {
+            None
+        } else {
+            Some(Constraint { types, ids, cells })
+        }
+    }
+
+    fn may_match(&self, entry: &BlobEntry) -> bool {
+        let has_types = (0..3)
+            .any(|i| self.types[i] && entry.ids[i].is_some());
+        let has_ids = self.ids.as_ref()
+            .map(|ids| entry.may_contain_any(ids))
+            .unwrap_or(true);
+        let has_cells = self.cells.as_ref()
+            .map(|cells| entry.cells.iter().any(|cell| cells.contains(cell)))
+            .unwrap_or(true);
+        has_types && has_ids && has_cells
+    }
+}
+
+/// Scan worker collecting a `BlobEntry` per blob
+struct IndexWorker {
+    locations: Arc<Locations>,
+    current: Option<(Arc<PathBuf>, BlobEntry, HashSet<Cell>)>,
+    entries: Vec<(Arc<PathBuf>, BlobEntry)>,
+}
+
+impl IndexWorker {
+    fn finish_blob(&mut self) {
+        if let Some((path, mut entry, cells)) = self.current.take() {
+            if entry.ids.iter().any(|ids| ids.is_some()) {
+                entry.cells = cells.into_iter().collect();
+                entry.cells.sort();
+                self.entries.push((path, entry));
+            }
+        }
+    }
+
+    fn into_entries(mut self) -> Vec<(Arc<PathBuf>, BlobEntry)> {
+        self.finish_blob();
+        self.entries
+    }
+}
+
+impl Worker for IndexWorker {
+    fn begin_blob(&mut self, path: &Arc<PathBuf>, offset: u64) {
+        self.finish_blob();
+        self.current = Some((path.clone(), BlobEntry::new(offset), HashSet::new()));
+    }
+
+    fn process(&mut self, primitive: &Primitive) {
+        let locations = &self.locations;
+        let (_, entry, cells) = self.current.as_mut()
+            .expect("Primitive outside of a blob");
+        let way_cells = |refs: &[i64], cells: &mut HashSet<Cell>| {
+            if let Some(bbox) = BoundingBox::planar_from_points(locations.ref_points(refs)) {
+                cells.extend(bbox_cells(&bbox));
+            }
+        };
+        match primitive {
+            &Primitive::Node(ref node) => {
+                entry.add_id(ItemId::new(ItemType::Node, node.id));
+                cells.insert(cell(node.lat, node.lon));
+            }
+            &Primitive::Way(ref way) => {
+                entry.add_id(ItemId::new(ItemType::Way, way.id));
+                way_cells(&way.refs().collect::<Vec<_>>(), cells);
+            }
+            &Primitive::Relation(ref rel) => {
+                entry.add_id(ItemId::new(ItemType::Relation, rel.id));
+                for (_, id, member_type) in rel.members() {
+                    match ItemType::from(member_type) {
+                        ItemType::Node =>
+                            if let Some((lat, lon)) = locations.node(id) {
+                                cells.insert(cell(lat, lon));
+                            },
+                        ItemType::Way =>
+                            if let Some(refs) = locations.way_refs(id) {
+                                way_cells(refs, cells);
+                            },
+                        _ =>
+                            (),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn save_entries(path: &::std::path::Path, entries: &[BlobEntry]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    write_u64(&mut file, entries.len() as u64)?;
+    for entry in entries {
+        write_u64(&mut file, entry.offset)?;
+        for ids in entry.ids.iter() {
+            let (min, max) = ids.unwrap_or((1, 0));
+            write_u64(&mut file, min)?;
+            write_u64(&mut file, max)?;
+        }
+        write_u64(&mut file, entry.cells.len() as u64)?;
+        for &(lat, lon) in entry.cells.iter() {
+            write_i32(&mut file, lat)?;
+            write_i32(&mut file, lon)?;
+        }
+    }
+    file.flush()
+}
+
+fn load_entries(path: &::std::path::Path) -> io::Result<Vec<BlobEntry>> {
+    let mut file = BufReader::new(File::open(path)?);
+    read_magic(&mut file, MAGIC)?;
+    let mut entries = vec![];
+    for _ in 0..read_u64(&mut file)? {
+        let mut entry = BlobEntry::new(read_u64(&mut file)?);
+        for ids in entry.ids.iter_mut() {
+            let min = read_u64(&mut file)?;
+            let max = read_u64(&mut file)?;
+            // Empty range for no elements of this type
+            if min <= max {
+                *ids = Some((min, max));
+            }
+        }
+        for _ in 0..read_u64(&mut file)? {
+            let lat = read_i32(&mut file)?;
+            let lon = read_i32(&mut file)?;
+            entry.cells.push((lat, lon));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+    use std::sync::Arc;
+    use std::path::PathBuf;
+    use std::collections::HashSet;
+    use item::{ItemId, ItemType};
+    use query::QueryTarget;
+    use filter::Context;
+    use trace_node::UniqueSet;
+    use ql::{Filter, QueryType, RecurseType};
+    use super::{BlobEntry, SpatialIndex, bbox_cells, cell, save_entries, load_entries};
+    use bbox::BoundingBox;
+
+    /// Dresden nodes in blob 100, ways in blob 200, Fiji nodes in
+    /// blob 300
+    fn index() -> SpatialIndex {
+        let mut dresden = BlobEntry::new(100);
+        dresden.ids[0] = Some((1, 1000));
+        dresden.cells = vec![cell(51.05, 13.74)];
+        let mut ways = BlobEntry::new(200);
+        ways.ids[1] = Some((1, 50));
+        ways.cells = vec![cell(51.05, 13.74), cell(-17.8, 178.0)];
+        let mut fiji = BlobEntry::new(300);
+        fiji.ids[0] = Some((1001, 2000));
+        fiji.cells = vec![cell(-17.8, 178.0), cell(-16.5, -179.9)];
+        SpatialIndex {
+            files: vec![(Arc::new(PathBuf::from("test.osm.pbf")), vec![dresden, ways, fiji])],
+        }
+    }
+
+    fn select(filters: Vec<Filter>) -> Option<Vec<u64>> {
+        let target = QueryTarget::Query {
+            filters: Arc::new(filters),
+            context: Arc::new(Context::new()),
+        };
+        index().select(&[(UniqueSet::new(1), target)])
+            .map(|blobs| blobs.into_iter().map(|(_, offset)| offset).collect())
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(select(vec![Filter::TagExist { k: ::ql::TagSpec::from_string("name") }]), None);
+        assert_eq!(select(vec![Filter::QueryType(QueryType::Node)]), Some(vec![100, 300]));
+        assert_eq!(select(vec![
+            Filter::QueryType(QueryType::Node),
+            Filter::BoundingBox { s: 51.0, w: 13.6, n: 51.1, e: 13.8 },
+        ]), Some(vec![100]));
+        assert_eq!(select(vec![
+            Filter::QueryType(QueryType::NWR),
+            Filter::BoundingBox { s: 51.0, w: 13.6, n: 51.1, e: 13.8 },
+        ]), Some(vec![100, 200]));
+        assert_eq!(select(vec![
+            Filter::QueryType(QueryType::Node),
+            Filter::Id(1500),
+        ]), Some(vec![300]));
+        assert_eq!(select(vec![
+            Filter::QueryType(QueryType::Node),
+            Filter::Around { radius: 1000.0, line: vec![(51.05, 13.74)] },
+        ]), Some(vec![100]));
+    }
+
+    #[test]
+    fn test_select_locations() {
+        let locations = |filters: Vec<Filter>| index().select_locations(&[&filters[..]])
+            .map(|blobs| blobs.into_iter().map(|(_, offset)| offset).collect::<Vec<_>>());
+        let dresden = Filter::BoundingBox { s: 51.0, w: 13.6, n: 51.1, e: 13.8 };
+        assert_eq!(locations(vec![Filter::TagExist { k: ::ql::TagSpec::from_string("name") }]), None);
+        // The ways also reach Fiji
+        assert_eq!(locations(vec![Filter::QueryType(QueryType::Way), dresden.clone()]), Some(vec![100, 200, 300]));
+        assert_eq!(locations(vec![Filter::QueryType(QueryType::Node), Filter::Id(1500)]), Some(vec![200, 300]));
+    }
+
+    #[test]
+    fn test_select_antimeridian() {
+        assert_eq!(select(vec![
+            Filter::QueryType(QueryType::Node),
+            Filter::BoundingBox { s: -17.0, w: 179.0, n: -16.0, e: -179.0 },
+        ]), Some(vec![300]));
+        assert_eq!(bbox_cells(&BoundingBox::new(0.0, 179.6, 0.1, -179.6)), vec![
+            (0, 359), (0, -360),
+        ]);
+    }
+
+    #[test]
+    fn test_select_recurse() {
+        let mut ids = HashSet::new();
+        ids.insert(ItemId::new(ItemType::Way, 10));
+        let down = QueryTarget::Recurse { recurse_type: RecurseType::Down, ids: Arc::new(ids.clone()) };
+        let blobs = index().select(&[(UniqueSet::new(1), down)]).unwrap();
+        assert_eq!(blobs.into_iter().map(|(_, offset)| offset).collect::<Vec<_>>(), vec![200]);
+        let up = QueryTarget::Recurse { recurse_type: RecurseType::Up, ids: Arc::new(ids) };
+        assert!(index().select(&[(UniqueSet::new(1), up)]).is_none());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = env::temp_dir().join(format!("underpass-turbo-test-{}.index", process::id()));
+        let entries = index().files.remove(0).1;
+        save_entries(&path, &entries).unwrap();
+        let loaded = load_entries(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), entries);
+    }
+}
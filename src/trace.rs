@@ -121,8 +121,14 @@ fn trace_statement_spec(statement_spec: StatementSpec, tracer: &mut Tracer) -> U
                 node, output)
         }
         Statement::Query { filters } => {
-            let node = Process::Query { filters };
-            let input_sets = HashSet::new();
+            let sets = filters.iter()
+                .filter_map(|filter| filter.input_set())
+                .map(|name| (name.clone(), tracer.get_set(name).clone()))
+                .collect::<Vec<_>>();
+            let input_sets = sets.iter()
+                .map(|&(_, set)| set)
+                .collect();
+            let node = Process::Query { filters, sets };
             tracer.add_node_with_sets(input_sets, node, output)
         }
         Statement::Recurse(rt) => {
@@ -143,20 +149,23 @@ fn trace_statement_spec(statement_spec: StatementSpec, tracer: &mut Tracer) -> U
             tracer.link(input_set.clone(), output);
             input_set
         }
-        Statement::Output => {
-            let node = Process::Output;
+        Statement::IsInArea { lat_lon } => {
+            let node = Process::IsIn { lat_lon };
+            tracer.add_node(statement_inputs.iter(), node, output)
+        }
+        Statement::Output { geometry } => {
+            let node = Process::Output { geometry };
             tracer.add_node(statement_inputs.iter(), node, output)
         }
-        _ =>
-            panic!("Not implemented: {:?}", statement),
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{Input, SetName, StatementSpec, Statement, Process};
+    use super::{SetName, StatementSpec, Statement, Process};
     use super::{trace, TraceNode};
+    use ql::{Filter, QueryType, OutputGeometry};
 
     #[test]
     fn test_trace_simple() {
@@ -168,19 +177,74 @@ mod tests {
             },
             StatementSpec {
                 inputs: vec![SetName::default()],
-                statement: Statement::Output,
+                statement: Statement::Output { geometry: OutputGeometry::None },
                 output: SetName::default(),
             },
         ].into_iter().cloned());
         let output_nodes = nodes.iter()
-            .filter(|(_, node)| node.process == Process::Output)
+            .filter(|(_, node)| node.process.is_output())
             .collect::<Vec<_>>();
         assert_eq!(output_nodes.len(), 1);
-        let output_inputs = &output_nodes[0].1.inputs;
+        let output_inputs = &output_nodes[0].1.input_sets;
         let query_nodes = nodes.iter()
             .filter(|(output, _)| output_inputs.contains(*output))
             .collect::<Vec<_>>();
         assert_eq!(query_nodes.len(), 1);
-        assert_eq!(query_nodes[0].1.process, Process::Query { filters: vec![] });
+        assert_eq!(query_nodes[0].1.process, Process::Query { filters: vec![], sets: vec![] });
+    }
+
+    #[test]
+    fn test_trace_intersection() {
+        let a = SetName::from("a".to_string());
+        let nodes = trace([
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query { filters: vec![] },
+                output: a.clone(),
+            },
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query {
+                    filters: vec![
+                        Filter::QueryType(QueryType::Node),
+                        Filter::Intersection(a.clone()),
+                    ],
+                },
+                output: SetName::default(),
+            },
+        ].into_iter().cloned());
+        let (a_set, _) = nodes.iter()
+            .find(|(_, node)| node.input_sets.is_empty())
+            .unwrap();
+        let (_, intersection_node) = nodes.iter()
+            .find(|(_, node)| node.input_sets.len() == 1)
+            .unwrap();
+        assert!(intersection_node.input_sets.contains(a_set));
+        match intersection_node.process {
+            Process::Query { ref sets, .. } =>
+                assert_eq!(sets, &vec![(a, *a_set)]),
+            _ =>
+                panic!("Not a query: {:?}", intersection_node.process),
+        }
+    }
+
+    #[test]
+    fn test_trace_is_in() {
+        let nodes = trace([
+            StatementSpec {
+                inputs: vec![],
+                statement: Statement::Query { filters: vec![] },
+                output: SetName::default(),
+            },
+            StatementSpec {
+                inputs: vec![SetName::default()],
+                statement: Statement::IsInArea { lat_lon: None },
+                output: SetName::default(),
+            },
+        ].into_iter().cloned());
+        let (_, is_in_node) = nodes.iter()
+            .find(|(_, node)| node.process == Process::IsIn { lat_lon: None })
+            .unwrap();
+        assert_eq!(is_in_node.input_sets.len(), 1);
     }
 }
@@ -1,4 +1,5 @@
 use std::collections::{HashMap, HashSet};
+use std::collections::hash_map;
 
 use process_node::Process;
 
@@ -51,10 +52,14 @@ impl Trace {
     pub fn get_by_output(&self, output: UniqueSet) -> Option<&TraceNode> {
         self.trace.get(&output)
     }
+
+    pub fn iter(&self) -> hash_map::Iter<UniqueSet, TraceNode> {
+        self.trace.iter()
+    }
     
     fn output_nodes(&self) -> Vec<(UniqueSet, &TraceNode)> {
         self.trace.iter()
-            .filter(|(_, node)| node.process == Process::Output)
+            .filter(|(_, node)| node.process.is_output())
             .map(|(output, node)| (*output, node))
             .collect()
     }
@@ -0,0 +1,83 @@
+use std::io;
+
+use item::{Item, ItemSpecific, ItemType};
+use set::Set;
+use locations::Locations;
+use ql::OutputGeometry;
+
+/// Serializes the sets of `out` statements into one document
+pub trait Writer {
+    fn begin(&mut self) -> io::Result<()>;
+    /// `locations` are available when the plan collected them
+    fn write(&mut self, set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> io::Result<()>;
+    fn end(&mut self) -> io::Result<()>;
+
+    /// Does the format need the geometry of ways and relations even
+    /// without `out geom`?
+    fn needs_locations(&self) -> bool {
+        false
+    }
+}
+
+/// Overpass element ordering: by type, then id
+pub fn sorted_items(set: &Set) -> Vec<&Item> {
+    let mut items = set.iter().collect::<Vec<_>>();
+    items.sort_by_key(|item| item.item_id());
+    items
+}
+
+/// By key, for reproducible output
+pub fn sorted_tags(item: &Item) -> Vec<(&str, &str)> {
+    let mut tags = item.tags.iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .collect::<Vec<_>>();
+    tags.sort();
+    tags
+}
+
+/// Element name in XML, `type` in JSON
+pub fn type_name(item: &Item) -> &str {
+    match item.specific() {
+        &ItemSpecific::Derived { ref derived_type } =>
+            derived_type,
+        _ =>
+            item_type_name(item.item_type()),
+    }
+}
+
+pub fn item_type_name(item_type: ItemType) -> &'static str {
+    match item_type {
+        ItemType::Node => "node",
+        ItemType::Way => "way",
+        ItemType::Relation => "relation",
+        ItemType::Area => "area",
+        ItemType::Derived => "derived",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use super::sorted_items;
+
+    #[test]
+    fn test_sorted_items() {
+        let mut set = Set::empty();
+        set.insert(Item::new(3, HashMap::new(), ItemSpecific::Way { refs: vec![] }));
+        set.insert(Item::new(2, HashMap::new(), ItemSpecific::Relation { members: vec![] }));
+        set.insert(Item::new(5, HashMap::new(), ItemSpecific::Node { lat: 0.0, lon: 0.0 }));
+        set.insert(Item::new(1, HashMap::new(), ItemSpecific::Way { refs: vec![] }));
+        let ids = sorted_items(&set).iter()
+            .map(|item| item.item_id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![
+            ItemId::new(ItemType::Node, 5),
+            ItemId::new(ItemType::Way, 1),
+            ItemId::new(ItemType::Way, 3),
+            ItemId::new(ItemType::Relation, 2),
+        ]);
+    }
+}
@@ -0,0 +1,221 @@
+use std::io::{self, Write};
+
+use item::{Item, ItemSpecific, ItemType};
+use set::Set;
+use bbox::BoundingBox;
+use locations::Locations;
+use ql::OutputGeometry;
+use output::{self, Geometry};
+use writer::{Writer, sorted_items, sorted_tags, type_name, item_type_name};
+
+/// OSM XML, as read by JOSM
+pub struct XmlWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> XmlWriter<W> {
+    pub fn new(out: W) -> Self {
+        XmlWriter { out }
+    }
+
+    fn write_item(&mut self, item: &Item, geometry: Option<Geometry>) -> io::Result<()> {
+        let mut attrs = format!("id=\"{}\"", item.id);
+        if let Some((lat, lon)) = item.get_lat_lon() {
+            attrs.push_str(&format!(" lat=\"{:.7}\" lon=\"{:.7}\"", lat, lon));
+        }
+
+        let mut children = vec![];
+        match geometry {
+            Some(Geometry::Center(lat, lon)) =>
+                children.push(format!("<center lat=\"{:.7}\" lon=\"{:.7}\"/>", lat, lon)),
+            Some(Geometry::Bounds(ref bounds)) |
+            Some(Geometry::Line { ref bounds, .. }) |
+            Some(Geometry::Members { bounds: Some(ref bounds), .. }) =>
+                children.push(bounds_element(bounds)),
+            _ => {}
+        }
+        match item.specific() {
+            &ItemSpecific::Way { ref refs } => {
+                let points = match geometry {
+                    Some(Geometry::Line { ref points, .. }) => Some(points),
+                    _ => None,
+                };
+                for (i, node_ref) in refs.iter().enumerate() {
+                    let point = points.and_then(|points| points[i]);
+                    children.push(format!("<nd ref=\"{}\"{}/>", node_ref, point_attrs(point)));
+                }
+            }
+            &ItemSpecific::Relation { ref members } => {
+                let member_points = match geometry {
+                    Some(Geometry::Members { ref members, .. }) => Some(members),
+                    _ => None,
+                };
+                for (i, &(ref role, ref member)) in members.iter().enumerate() {
+                    let element = format!(
+                        "<member type=\"{}\" ref=\"{}\" role=\"{}\"",
+                        item_type_name(member.item_type), member.id, escape(role)
+                    );
+                    match member_points.map(|member_points| &member_points[i]) {
+                        Some(points) if member.item_type == ItemType::Node && points.len() == 1 =>
+                            children.push(format!("{}{}/>", element, point_attrs(points[0]))),
+                        Some(points) if !points.is_empty() => {
+                            children.push(format!("{}>", element));
+                            for point in points {
+                                children.push(format!("  <nd{}/>", point_attrs(*point)));
+                            }
+                            children.push("</member>".to_owned());
+                        }
+                        _ =>
+                            children.push(format!("{}/>", element)),
+                    }
+                }
+            }
+            _ => {}
+        }
+        for (k, v) in sorted_tags(item) {
+            children.push(format!("<tag k=\"{}\" v=\"{}\"/>", escape(k), escape(v)));
+        }
+
+        let name = type_name(item);
+        if children.is_empty() {
+            writeln!(self.out, "  <{} {}/>", name, attrs)
+        } else {
+            writeln!(self.out, "  <{} {}>", name, attrs)?;
+            for child in children {
+                writeln!(self.out, "    {}", child)?;
+            }
+            writeln!(self.out, "  </{}>", name)
+        }
+    }
+}
+
+impl<W: Write> Writer for XmlWriter<W> {
+    fn begin(&mut self) -> io::Result<()> {
+        writeln!(self.out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(self.out, "<osm version=\"0.6\" generator=\"Underpass Turbo\">")
+    }
+
+    fn write(&mut self, set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> io::Result<()> {
+        for item in sorted_items(set) {
+            let item_geometry = locations
+                .and_then(|locations| output::resolve(item, geometry, locations));
+            self.write_item(item, item_geometry)?;
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        writeln!(self.out, "</osm>")?;
+        self.out.flush()
+    }
+}
+
+fn bounds_element(bounds: &BoundingBox) -> String {
+    format!(
+        "<bounds minlat=\"{:.7}\" minlon=\"{:.7}\" maxlat=\"{:.7}\" maxlon=\"{:.7}\"/>",
+        bounds.s, bounds.w, bounds.n, bounds.e
+    )
+}
+
+/// Missing and clipped points have no coordinates
+fn point_attrs(point: Option<(f64, f64)>) -> String {
+    match point {
+        Some((lat, lon)) => format!(" lat=\"{:.7}\" lon=\"{:.7}\"", lat, lon),
+        None => String::new(),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            '\n' => escaped.push_str("&#10;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use item::{Item, ItemSpecific, ItemId, ItemType};
+    use set::Set;
+    use locations::Locations;
+    use ql::OutputGeometry;
+    use writer::Writer;
+    use super::XmlWriter;
+
+    fn write(set: &Set, geometry: &OutputGeometry, locations: Option<&Locations>) -> String {
+        let mut out = vec![];
+        {
+            let mut writer = XmlWriter::new(&mut out);
+            writer.begin().unwrap();
+            writer.write(set, geometry, locations).unwrap();
+            writer.end().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn data() -> Set {
+        let mut set = Set::empty();
+        let mut tags = HashMap::new();
+        tags.insert("name".to_owned(), "Café \"<Z>\" & Co".to_owned());
+        tags.insert("amenity".to_owned(), "cafe".to_owned());
+        set.insert(Item::new(2, tags, ItemSpecific::Node { lat: 51.05, lon: 13.74 }));
+        set.insert(Item::new(1, HashMap::new(), ItemSpecific::Node { lat: 51.0, lon: 13.7 }));
+        set.insert(Item::new(10, HashMap::new(), ItemSpecific::Way { refs: vec![1, 2] }));
+        set.insert(Item::new(20, HashMap::new(), ItemSpecific::Relation {
+            members: vec![("outer".to_owned(), ItemId::new(ItemType::Way, 10))],
+        }));
+        set
+    }
+
+    #[test]
+    fn test_xml() {
+        assert_eq!(write(&data(), &OutputGeometry::None, None), r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6" generator="Underpass Turbo">
+  <node id="1" lat="51.0000000" lon="13.7000000"/>
+  <node id="2" lat="51.0500000" lon="13.7400000">
+    <tag k="amenity" v="cafe"/>
+    <tag k="name" v="Café &quot;&lt;Z&gt;&quot; &amp; Co"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role="outer"/>
+  </relation>
+</osm>
+"#);
+    }
+
+    #[test]
+    fn test_xml_geom() {
+        let mut locations = Locations::new();
+        locations.insert_node(1, 51.0, 13.7);
+        locations.insert_node(2, 51.05, 13.74);
+        locations.insert_way(10, vec![1, 2]);
+        let output = write(&data(), &OutputGeometry::Geom { clip: None }, Some(&locations));
+        assert!(output.contains(r#"  <way id="10">
+    <bounds minlat="51.0000000" minlon="13.7000000" maxlat="51.0500000" maxlon="13.7400000"/>
+    <nd ref="1" lat="51.0000000" lon="13.7000000"/>
+    <nd ref="2" lat="51.0500000" lon="13.7400000"/>
+  </way>
+"#));
+        assert!(output.contains(r#"    <member type="way" ref="10" role="outer">
+      <nd lat="51.0000000" lon="13.7000000"/>
+      <nd lat="51.0500000" lon="13.7400000"/>
+    </member>
+"#));
+        let output = write(&data(), &OutputGeometry::Center, Some(&locations));
+        assert!(output.contains(r#"<center lat="51.0250000" lon="13.7200000"/>"#));
+    }
+}